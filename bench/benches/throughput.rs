@@ -0,0 +1,141 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use simplebgc_api::{
+    encode_command, parse_command, APIv2, Axis, Control, ControlAxis, ControlMode, FrameDecoder,
+    GimbalSpeed, OutgoingCommand, RealtimeData3, Transmit,
+};
+
+/// Counts allocations so the decode benchmarks can report how many each read makes
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations<F: FnMut()>(mut f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// Length of a `CMD_REALTIME_DATA_3` payload
+const REALTIME_DATA_3_LEN: usize = 63;
+
+fn control() -> Control {
+    Control::axis_only(
+        Axis::Yaw,
+        ControlMode::SpeedAngle,
+        ControlAxis {
+            speed: GimbalSpeed(300),
+            angle: 4096,
+        },
+    )
+}
+
+fn realtime_data_3() -> OutgoingCommand {
+    OutgoingCommand {
+        id: 23,
+        sub_id: None,
+        payload: vec![0; REALTIME_DATA_3_LEN],
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let control = control();
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("control_v2", |b| {
+        b.iter(|| {
+            let cmd = OutgoingCommand::from_command(black_box(&control)).unwrap();
+            encode_command::<APIv2>(&cmd).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let payload = realtime_data_3().payload;
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("realtime_data_3", |b| {
+        b.iter(|| parse_command(23, black_box(&payload)).unwrap())
+    });
+    group.bench_function("realtime_data_3_typed", |b| {
+        b.iter(|| RealtimeData3::from_slice(black_box(&payload)).unwrap())
+    });
+    let mut data = RealtimeData3::from_slice(&payload).unwrap();
+    group.bench_function("realtime_data_3_read_into", |b| {
+        b.iter(|| data.read_into(&mut black_box(&payload[..])).unwrap())
+    });
+    group.finish();
+
+    let from_reader = allocations(|| {
+        black_box(RealtimeData3::from_slice(&payload).unwrap());
+    });
+    let read_into = allocations(|| data.read_into(&mut &payload[..]).unwrap());
+    println!(
+        "realtime_data_3 allocations per frame: from_reader {}, read_into {}",
+        from_reader, read_into
+    );
+}
+
+fn decoder(c: &mut Criterion) {
+    let control = OutgoingCommand::from_command(&control()).unwrap();
+    let realtime = realtime_data_3();
+    let mut bytes = Vec::new();
+    for i in 0..100 {
+        let cmd = if i % 2 == 0 { &control } else { &realtime };
+        bytes.extend(encode_command::<APIv2>(cmd).unwrap());
+        // Line noise between some of the frames
+        if i % 10 == 0 {
+            bytes.extend([0x00, 0xFF, 0x24, 0x01]);
+        }
+    }
+
+    let mut group = c.benchmark_group("frame_decoder");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("mixed_frames", |b| {
+        b.iter(|| {
+            let mut decoder = FrameDecoder::<APIv2>::new();
+            let mut frames = 0;
+            for chunk in black_box(&bytes).chunks(64) {
+                decoder.push(chunk).unwrap();
+                frames += decoder.frames().count();
+            }
+            frames
+        })
+    });
+    group.finish();
+}
+
+/// `[u8; N]` is read with a single `read_exact`, while other arrays are read element by element
+fn arrays(c: &mut Criterion) {
+    let bytes = [0x11; 256];
+    let mut group = c.benchmark_group("arrays");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("u8_read_exact", |b| {
+        b.iter(|| <[u8; 256]>::from_slice(black_box(&bytes)).unwrap())
+    });
+    group.bench_function("i8_per_element", |b| {
+        b.iter(|| <[i8; 256]>::from_slice(black_box(&bytes)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode, decoder, arrays);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use simplebgc_api::{APIv1, APIv2, FrameDecoder, Parser};
+
+/// Feeds the input in chunks sized by its first byte and decodes until more bytes are needed,
+/// checking that every decoded frame or error consumes input.
+fn decode_all<P: Parser>(data: &[u8]) {
+    let (chunk_len, data) = match data.split_first() {
+        Some((len, data)) => (*len as usize + 1, data),
+        None => return,
+    };
+    let mut decoder = FrameDecoder::<P>::new();
+    for chunk in data.chunks(chunk_len) {
+        // Overflowing is a valid outcome for some inputs, it just empties the buffer
+        let _ = decoder.push(chunk);
+        let mut buffered = decoder.buffered();
+        while decoder.next_frame().is_some() {
+            assert!(decoder.buffered() < buffered, "decoder made no progress");
+            buffered = decoder.buffered();
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    decode_all::<APIv1>(data);
+    decode_all::<APIv2>(data);
+});
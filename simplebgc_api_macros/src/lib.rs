@@ -3,6 +3,8 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::{quote, ToTokens};
 use syn;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
 
 // TODO: Figure out path handling
 // TODO: Add range validation
@@ -15,12 +17,112 @@ fn to_token(i: usize, f: &syn::Field) -> impl ToTokens {
     }
 }
 
-#[proc_macro_derive(Transmit, attributes(range))]
+/// One `name` or `name = value` item in a `#[transmit(..)]` attribute
+struct TransmitArg {
+    name: syn::Ident,
+    value: Option<syn::Expr>,
+}
+
+impl Parse for TransmitArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        // Allows keywords as names
+        let name = input.call(syn::Ident::parse_any)?;
+        let value = if input.peek(syn::Token![=]) {
+            input.parse::<syn::Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(Self { name, value })
+    }
+}
+
+/// Options given to a field with `#[transmit(..)]`
+#[derive(Default)]
+struct FieldOptions {
+    /// Not sent, and set to its default when read
+    skip: bool,
+    /// Only sent when this is true, with the field holding an `Option`
+    condition: Option<syn::Expr>,
+    /// Real units per raw unit, for fixed-point fields
+    scale: Option<syn::Expr>,
+    /// The real value when the raw value is zero
+    offset: Option<syn::Expr>,
+}
+
+/// Parses every `#[transmit(..)]` attribute in `attrs`
+fn transmit_args(attrs: &[syn::Attribute]) -> Vec<TransmitArg> {
+    use syn::punctuated::Punctuated;
+    let parser = Punctuated::<TransmitArg, syn::Token![,]>::parse_terminated;
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("transmit"))
+        .flat_map(|attr| attr.parse_args_with(parser).unwrap())
+        .collect()
+}
+
+fn field_options(field: &syn::Field) -> FieldOptions {
+    let mut options = FieldOptions::default();
+    for arg in transmit_args(&field.attrs) {
+        match (arg.name.to_string().as_str(), arg.value) {
+            ("skip", None) => options.skip = true,
+            (
+                "if",
+                Some(syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(expr),
+                    ..
+                })),
+            ) => options.condition = Some(expr.parse().unwrap()),
+            ("scale", Some(scale)) => options.scale = Some(scale),
+            ("offset", Some(offset)) => options.offset = Some(offset),
+            (name, _) => panic!("unknown transmit option `{}`", name),
+        }
+    }
+    options
+}
+
+/// Derives `Transmit` for a struct or a fieldless enum. Generic type parameters are required to be
+/// `Transmit` too.
+///
+/// Struct fields are read and written in declaration order. They can be annotated with:
+/// - `#[range(a..=b, ..)]` to reject values outside all of the given ranges
+/// - `#[transmit(skip)]` to leave a field off the wire, setting it to `Default::default()` when
+///   read
+/// - `#[transmit(if = "expr")]` on an `Option<T>` field to send it only when `expr` is true. The
+///   expression can use the fields declared before it by name, as references. Reading gives
+///   `None` when the expression is false, and writing fails unless the field is `Some` exactly when
+///   the expression is true.
+/// - `#[transmit(scale = s, offset = o)]` on an integer field holding a fixed-point value, where
+///   either can be left out. This generates `<field>_scaled`, returning `raw * s + o` as an `f64`,
+///   and `set_<field>_scaled`, which rounds to the nearest raw value and fails if that doesn't fit
+///   in the field's type. The wire format is unchanged.
+///
+/// `#[transmit(try_new)]` on a struct with named fields generates a `try_new` constructor taking
+/// every field in order and returning an error if `validate` fails, so bad values are caught when
+/// the struct is built instead of when it's sent. Skipped fields and fields whose name starts with
+/// `_`, such as reserved bytes, are left out and set to their default.
+///
+/// `validate` checks the ranges and then validates every field that is sent. Errors reading a field
+/// are prefixed with the struct and field name, such as `MotorStatus.p: `. Checks involving more
+/// than one field can be added with `#[transmit(validate = path)]` on the struct, naming a
+/// function taking `&Self` and returning `io::Result<()>`, which runs after the other checks.
+///
+/// Enums need a `#[repr(T)]` attribute and an explicit discriminant on every variant. Reading any
+/// other value is an error, unless one variant is marked `#[transmit(other)]`. That variant holds
+/// the raw value, such as `Other(u8)`, and must not hold the value of another variant. The other
+/// variants are listed in a hidden `TRANSMIT_VARIANTS` constant for testing.
+#[proc_macro_derive(Transmit, attributes(range, transmit))]
 pub fn command_part_derive(input: TokenStream) -> TokenStream {
     // Construct a representation of Rust code as a syntax tree that we can manipulate
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
 
     let name = &ast.ident;
+    // Type parameters must be `Transmit` themselves for the fields holding them to be sent
+    let mut generics = ast.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!(Transmit));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
     let gen = match &ast.data {
         syn::Data::Struct(data) => {
@@ -31,8 +133,100 @@ pub fn command_part_derive(input: TokenStream) -> TokenStream {
                 .map(|(i, f)| to_token(i, f))
                 .collect();
 
+            // Fields are read into locals in wire order and then the struct is built from them
+            let locals: Vec<_> = (0..data.fields.len())
+                .map(|i| quote::format_ident!("__field{}", i))
+                .collect();
+            let construct = match &data.fields {
+                syn::Fields::Named(_) => quote!(Self { #( #fields: #locals ),* }),
+                syn::Fields::Unnamed(_) => quote!(Self( #( #locals ),* )),
+                syn::Fields::Unit => quote!(Self),
+            };
+
             // TODO: Collect as many errors as possible by not panicking
-            let types = data.fields.iter().map(|field| &field.ty);
+            let options: Vec<_> = data.fields.iter().map(field_options).collect();
+            // Once read or written, named fields are bound to references so conditions can use them
+            let bindings: Vec<_> = data
+                .fields
+                .iter()
+                .map(|f| {
+                    f.ident
+                        .as_ref()
+                        .map(|ident| quote!(#[allow(unused_variables)] let #ident = ))
+                })
+                .collect();
+            // Read errors say which field they happened in, like `MotorStatus.p: <error>`
+            let contexts: Vec<_> = fields.iter().map(|field| {
+                let context = format!("{}.{}: ", name, field.to_token_stream());
+                quote! {
+                    map_err(|err| ::std::io::Error::new(err.kind(), format!("{}{}", #context, err)))
+                }
+            }).collect();
+            // `read_into` overwrites each field in place, so arrays and buffers are reused
+            let reads_into = fields
+                .iter()
+                .zip(&contexts)
+                .zip(&options)
+                .zip(&bindings)
+                .map(|(((field, context), options), binding)| {
+                    let read = if options.skip {
+                        quote!(self.#field = ::std::default::Default::default();)
+                    } else if let Some(condition) = &options.condition {
+                        quote! {
+                            self.#field = if #condition {
+                                ::std::option::Option::Some(Transmit::from_reader(__reader).#context?)
+                            } else {
+                                ::std::option::Option::None
+                            };
+                        }
+                    } else {
+                        quote!(Transmit::read_into(&mut self.#field, __reader).#context?;)
+                    };
+                    let bind = binding.as_ref().map(|binding| quote!(#binding &self.#field;));
+                    quote!(#read #bind)
+                });
+            let reads = data
+                .fields
+                .iter()
+                .zip(&contexts)
+                .zip(&locals)
+                .zip(&options)
+                .zip(&bindings)
+                .map(|((((f, context), local), options), binding)| {
+                    let ty = &f.ty;
+                    let read = if options.skip {
+                        quote!(let #local: #ty = ::std::default::Default::default();)
+                    } else if let Some(condition) = &options.condition {
+                        quote! {
+                            let #local: #ty = if #condition {
+                                ::std::option::Option::Some(Transmit::from_reader(__reader).#context?)
+                            } else {
+                                ::std::option::Option::None
+                            };
+                        }
+                    } else {
+                        quote!(let #local = <#ty>::from_reader(__reader).#context?;)
+                    };
+                    let bind = binding.as_ref().map(|binding| quote!(#binding &#local;));
+                    quote!(#read #bind)
+                });
+            let writes = fields.iter().zip(&options).zip(&bindings).map(|((field, options), binding)| {
+                let write = if options.skip {
+                    quote!()
+                } else if let Some(condition) = &options.condition {
+                    quote! {
+                        match (#condition, &self.#field) {
+                            (true, Some(value)) => value.to_writer(__writer)?,
+                            (false, None) => {}
+                            _ => return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, "conditional field must be set exactly when its condition holds")),
+                        }
+                    }
+                } else {
+                    quote!(self.#field.to_writer(__writer)?;)
+                };
+                let bind = binding.as_ref().map(|binding| quote!(#binding &self.#field;));
+                quote!(#write #bind)
+            });
 
             // TODO: Handle ranges
             let checks = data.fields.iter().enumerate().map(|(i, f)| {
@@ -48,33 +242,152 @@ pub fn command_part_derive(input: TokenStream) -> TokenStream {
                     })
                     .map(|punctuated| {
                         let ranges = punctuated.iter();
-                        quote! {
-                            if #(!(#ranges).contains(&self.#ident))&&* {
-                                return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "data outside of valid range"));
+                        // Arrays are checked element by element, naming the first one out of range
+                        if let syn::Type::Array(_) = f.ty {
+                            let context = format!("{}.{}", name, ident.to_token_stream());
+                            quote! {
+                                for (__index, __elem) in self.#ident.iter().enumerate() {
+                                    if #(!(#ranges).contains(__elem))&&* {
+                                        return Err(::std::io::Error::new(
+                                            ::std::io::ErrorKind::InvalidData,
+                                            format!("data outside of valid range: {}[{}] is {}", #context, __index, __elem),
+                                        ));
+                                    }
+                                }
+                            }
+                        } else {
+                            quote! {
+                                if #(!(#ranges).contains(&self.#ident))&&* {
+                                    return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "data outside of valid range"));
+                                }
                             }
                         }
                     });
-                quote!(#(#check_this)*)
+                // Values that are made of other values check those too
+                let check_nested = if options[i].skip {
+                    quote!()
+                } else if options[i].condition.is_some() {
+                    quote!(if let ::std::option::Option::Some(value) = &self.#ident { value.validate()?; })
+                } else {
+                    quote!(Transmit::validate(&self.#ident)?;)
+                };
+                quote!(#(#check_this)* #check_nested)
             });
 
+            let scaled = data
+                .fields
+                .iter()
+                .zip(&options)
+                .filter(|(_, options)| options.scale.is_some() || options.offset.is_some())
+                .map(|(f, options)| {
+                    let ident = f.ident.as_ref().expect("scaled fields must be named");
+                    let ty = &f.ty;
+                    let scale = options.scale.as_ref().map_or_else(|| quote!(1.0), |scale| quote!(#scale));
+                    let offset = options.offset.as_ref().map_or_else(|| quote!(0.0), |offset| quote!(#offset));
+                    let getter = quote::format_ident!("{}_scaled", ident);
+                    let setter = quote::format_ident!("set_{}_scaled", ident);
+                    let get_doc = format!("`{}` in real units", ident);
+                    let set_doc = format!("Sets `{}` from real units, rounding to the nearest raw value.", ident);
+                    quote! {
+                        #[doc = #get_doc]
+                        pub fn #getter(&self) -> f64 {
+                            self.#ident as f64 * (#scale) + (#offset)
+                        }
+
+                        #[doc = #set_doc]
+                        pub fn #setter(&mut self, value: f64) -> ::std::io::Result<()> {
+                            let raw = ((value - (#offset)) / (#scale)).round();
+                            if !(raw >= <#ty>::MIN as f64 && raw <= <#ty>::MAX as f64) {
+                                return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, "scaled value out of range"));
+                            }
+                            self.#ident = raw as #ty;
+                            Ok(())
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            let scaled = if scaled.is_empty() {
+                quote!()
+            } else {
+                quote! {
+                    impl #impl_generics #name #ty_generics #where_clause {
+                        #( #scaled )*
+                    }
+                }
+            };
+
+            let mut try_new = false;
+            let mut custom_validate = None;
+            for arg in transmit_args(&ast.attrs) {
+                match (arg.name.to_string().as_str(), arg.value) {
+                    ("try_new", None) => try_new = true,
+                    ("validate", Some(check)) => custom_validate = Some(check),
+                    (name, _) => panic!("unknown transmit option `{}`", name),
+                }
+            }
+            // Checks across fields run once every field is known to be valid on its own
+            let custom_validate = custom_validate.map(|check| quote!((#check)(self)?;));
+            let try_new = if try_new {
+                let (params, values): (Vec<_>, Vec<_>) = data
+                    .fields
+                    .iter()
+                    .zip(&options)
+                    .map(|(f, options)| {
+                        let ident = f
+                            .ident
+                            .as_ref()
+                            .expect("#[transmit(try_new)] requires named fields");
+                        if options.skip || ident.to_string().starts_with('_') {
+                            (None, quote!(#ident: ::std::default::Default::default()))
+                        } else {
+                            let ty = &f.ty;
+                            (Some(quote!(#ident: #ty)), quote!(#ident))
+                        }
+                    })
+                    .unzip();
+                let params = params.iter().flatten();
+                let doc = format!(
+                    "Creates a `{}`, returning an error if any field is out of range.",
+                    name
+                );
+                quote! {
+                    impl #impl_generics #name #ty_generics #where_clause {
+                        #[doc = #doc]
+                        #[allow(clippy::too_many_arguments)]
+                        pub fn try_new(#( #params ),*) -> ::std::io::Result<Self> {
+                            let __data = Self { #( #values ),* };
+                            Transmit::validate(&__data)?;
+                            Ok(__data)
+                        }
+                    }
+                }
+            } else {
+                quote!()
+            };
+
             quote! {
-                impl Transmit for #name {
+                #try_new
+                #scaled
+
+                impl #impl_generics Transmit for #name #ty_generics #where_clause {
                     fn validate(&self) -> ::std::io::Result<()> {
                         #(#checks)*
+                        #custom_validate
                         Ok(())
                     }
-                    fn from_reader<R: ::byteorder::ReadBytesExt>(reader: &mut R) -> ::std::io::Result<Self> {
-                        // Safety: We immediately fill in all fields for the struct, guaranteed by
-                        let mut data: Self = unsafe {
-                            ::std::mem::MaybeUninit::uninit().assume_init()
-                        };
-                        #( data.#fields = <#types>::from_reader(reader)?; )*
-                        data.validate()?;
-                        Ok(data)
+                    fn from_reader<R: ::byteorder::ReadBytesExt>(__reader: &mut R) -> ::std::io::Result<Self> {
+                        #( #reads )*
+                        let __data = #construct;
+                        __data.validate()?;
+                        Ok(__data)
                     }
-                    fn to_writer<W: ::byteorder::WriteBytesExt>(&self, writer: &mut W) -> ::std::io::Result<()> {
+                    fn read_into<R: ::byteorder::ReadBytesExt>(&mut self, __reader: &mut R) -> ::std::io::Result<()> {
+                        #( #reads_into )*
+                        self.validate()
+                    }
+                    fn to_writer<W: ::byteorder::WriteBytesExt>(&self, __writer: &mut W) -> ::std::io::Result<()> {
                         self.validate()?;
-                        #( self.#fields.to_writer(writer)?; )*
+                        #( #writes )*
                         Ok(())
                     }
                 }
@@ -89,34 +402,76 @@ pub fn command_part_derive(input: TokenStream) -> TokenStream {
                 .map(|attr| attr.parse_args::<syn::TypePath>().unwrap())
                 .next()
                 .expect("derive(Transmit) requires a #[repr(T)] attribute for enums");
-            let variants: Vec<_> = data.variants.iter().map(|variant| &variant.ident).collect();
-            let discriminants: Vec<_> = data
-                .variants
+            let is_other = |variant: &&syn::Variant| {
+                transmit_args(&variant.attrs)
+                    .iter()
+                    .any(|arg| arg.name == "other" && arg.value.is_none())
+            };
+            let mut others = data.variants.iter().filter(is_other);
+            let other = others.next().map(|variant| &variant.ident);
+            if others.next().is_some() {
+                panic!("derive(Transmit) allows only one #[transmit(other)] variant");
+            }
+
+            let known: Vec<_> = data.variants.iter().filter(|v| !is_other(v)).collect();
+            let variants: Vec<_> = known.iter().map(|variant| &variant.ident).collect();
+            let discriminants: Vec<_> = known
                 .iter()
                 .map(|variant| {
-                    let (_eq, expr) = variant.discriminant.as_ref().unwrap();
+                    let (_eq, expr) = variant
+                        .discriminant
+                        .as_ref()
+                        .expect("derive(Transmit) requires explicit discriminants");
                     expr
                 })
                 .collect();
 
-            quote! {
-                impl Transmit for #name {
+            let (validate, read_other, write_other) = match other {
+                Some(other) => (
+                    quote! {
+                        match self {
+                            Self::#other(#( #discriminants )|*) => Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, "catch-all variant holds a known value")),
+                            _ => Ok(()),
+                        }
+                    },
+                    quote!(other => Ok(Self::#other(other)),),
+                    quote!(Self::#other(other) => *other,),
+                ),
+                None => (
                     // Enums can only be valid values
+                    quote!(Ok(())),
+                    quote!(_ => Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "read value does not match any enum variants")),),
+                    quote!(),
+                ),
+            };
+
+            quote! {
+                impl #impl_generics Transmit for #name #ty_generics #where_clause {
                     fn validate(&self) -> ::std::io::Result<()> {
-                        Ok(())
+                        #validate
                     }
                     fn from_reader<R: ::byteorder::ReadBytesExt>(reader: &mut R) -> ::std::io::Result<Self> {
-                        // Safety: We immediately fill in all fields for the struct, guaranteed by
                         match <#ty>::from_reader(reader)? {
                             #( #discriminants => Ok(Self::#variants), )*
-                            _ => Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "read value does not match any enum variants")),
+                            #read_other
                         }
                     }
                     fn to_writer<W: ::byteorder::WriteBytesExt>(&self, writer: &mut W) -> ::std::io::Result<()> {
-                        let val = match self { #( Self::#variants => #discriminants, )* };
+                        self.validate()?;
+                        let val = match self {
+                            #( Self::#variants => #discriminants, )*
+                            #write_other
+                        };
                         <#ty>::to_writer(&val, writer)
                     }
                 }
+
+                impl #impl_generics #name #ty_generics #where_clause {
+                    /// Every variant with a fixed value, so tests can check that each one reads
+                    /// back as itself
+                    #[doc(hidden)]
+                    pub const TRANSMIT_VARIANTS: &'static [Self] = &[ #( Self::#variants ),* ];
+                }
             }
         }
 
@@ -126,12 +481,21 @@ pub fn command_part_derive(input: TokenStream) -> TokenStream {
     gen.into()
 }
 
-#[proc_macro_derive(Command, attributes(id))]
+/// Derives `Command` from a `#[id(N)]` attribute and an optional `#[direction(..)]` attribute,
+/// which is one of `incoming`, `outgoing` or `both` and defaults to `both`. `Incoming` and
+/// `Outgoing` are implemented to match the direction.
+#[proc_macro_derive(Command, attributes(id, direction))]
 pub fn command_derive(input: TokenStream) -> TokenStream {
     // Construct a representation of Rust code as a syntax tree that we can manipulate
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
 
     let name = &ast.ident;
+    let mut generics = ast.generics.clone();
+    generics
+        .make_where_clause()
+        .predicates
+        .push(syn::parse_quote!(Self: Transmit));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let id: u8 = ast
         .attrs
         .iter()
@@ -140,12 +504,37 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
         .map(|lit| lit.base10_parse().unwrap())
         .next()
         .expect("derive(Command) requires a #[id(N)] attribute");
+    let direction = ast
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("direction"))
+        .map(|attr| attr.parse_args::<syn::Ident>().unwrap())
+        .next()
+        .map_or_else(|| "both".to_string(), |direction| direction.to_string());
+    let (direction, incoming, outgoing) = match direction.as_str() {
+        "incoming" => (quote!(Incoming), true, false),
+        "outgoing" => (quote!(Outgoing), false, true),
+        "both" => (quote!(Both), true, true),
+        other => panic!("unknown command direction `{}`", other),
+    };
+    // The marker traits let the compiler reject sending a command that is only ever received
+    let incoming = if incoming {
+        quote!(impl #impl_generics Incoming for #name #ty_generics #where_clause {})
+    } else {
+        quote!()
+    };
+    let outgoing = if outgoing {
+        quote!(impl #impl_generics Outgoing for #name #ty_generics #where_clause {})
+    } else {
+        quote!()
+    };
 
     // TODO: Should I actually get rid of the common thing and enforce that all commands are
     // command parts? Probably. That will require renaming the trait to reflect it's usage though.
     let gen = quote! {
-        impl Command for #name where Self: Transmit {
+        impl #impl_generics Command for #name #ty_generics #where_clause {
             const ID: u8 = #id;
+            const DIRECTION: Direction = Direction::#direction;
             fn parse_payload<R: ::byteorder::ReadBytesExt>(reader: &mut R) -> ::std::io::Result<Self> {
                 Self::from_reader(reader)
             }
@@ -153,6 +542,8 @@ pub fn command_derive(input: TokenStream) -> TokenStream {
                 self.to_writer(writer)
             }
         }
+        #incoming
+        #outgoing
     };
 
     gen.into()
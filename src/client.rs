@@ -0,0 +1,704 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::commands::{
+    BoardInfo, BoardInfo3, CmdError, Command, Confirm, IncomingCommand, OutgoingCommand,
+};
+use crate::decoder::FrameDecoder;
+use crate::log::Timestamped;
+use crate::parser::{encode_command, APIv2, ParseError, Parser};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Parse(ParseError),
+    /// The transport timed out before any frame arrived
+    NoResponse,
+    /// Frames arrived, but not the one being waited for. Holds their command ids in the order
+    /// they were received.
+    UnexpectedResponses(Vec<u8>),
+    /// A frame started arriving but no more of it came within the inter-byte timeout. The frame
+    /// was dropped and decoding resumes at the next start byte.
+    PartialFrameTimeout,
+    /// The board answered with `CMD_ERROR`
+    Board(CmdError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => err.fmt(f),
+            Self::NoResponse => write!(f, "no response from the board"),
+            Self::UnexpectedResponses(ids) => {
+                write!(f, "expected response not received, got ids {:?}", ids)
+            }
+            Self::PartialFrameTimeout => write!(f, "frame stopped arriving part way"),
+            Self::Board(err) => write!(f, "board reported an error: {}", err.error_code),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for ClientError {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        Self::Parse(err.into())
+    }
+}
+
+/// How long `send_confirmed` waits by default
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A blocking connection to a board over a transport such as a serial port.
+///
+/// Timeouts are left to the transport: a read that times out is returned as an IO error. Methods
+/// that wait for a particular response also give up after the response timeout, but can only
+/// notice it between reads, so the transport should still have a read timeout of its own.
+pub struct Client<T, P: Parser> {
+    transport: T,
+    decoder: FrameDecoder<P>,
+    response_timeout: Duration,
+    inter_byte_timeout: Option<Duration>,
+    /// When bytes were last received, for the inter-byte timeout
+    last_byte: Instant,
+    /// The wall-clock time of `last_byte`, for timestamping frames
+    last_byte_at: SystemTime,
+}
+
+/// Whether `confirm` confirms `sent` and echoes the CRC16 of the v2 frame `sent` was sent in, so
+/// the board is known to have received it intact. Confirmations without an echoed CRC, from
+/// firmware that doesn't send one, never verify.
+pub fn verify_confirm(sent: &OutgoingCommand, confirm: &Confirm) -> bool {
+    if confirm.cmd_id != sent.id {
+        return false;
+    }
+    let frame = match encode_command::<APIv2>(sent) {
+        Ok(frame) => frame,
+        Err(_) => return false,
+    };
+    let crc = u16::from_le_bytes([frame[frame.len() - 2], frame[frame.len() - 1]]);
+    confirm.echoed_crc() == Some(crc)
+}
+
+fn timed_out(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock
+}
+
+impl<T: Read + Write, P: Parser> Client<T, P> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            decoder: FrameDecoder::new(),
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            inter_byte_timeout: None,
+            last_byte: Instant::now(),
+            last_byte_at: SystemTime::now(),
+        }
+    }
+
+    /// Sets how long `send_confirmed` waits for the confirmation.
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.response_timeout = timeout;
+    }
+
+    /// Sets how long a frame may stall part way before `receive` drops it and returns
+    /// `PartialFrameTimeout`, or `None` to wait for the rest of it indefinitely, which is the
+    /// default. The stall is only noticed when a read of the transport times out, so the
+    /// transport's read timeout should be shorter than this.
+    pub fn set_inter_byte_timeout(&mut self, timeout: Option<Duration>) {
+        self.inter_byte_timeout = timeout;
+    }
+
+    /// Passes frames with the command `id` to `handler` instead of returning them, see
+    /// `FrameDecoder::register_handler`.
+    pub fn register_handler<F>(&mut self, id: u8, handler: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.decoder.register_handler(id, handler);
+    }
+
+    /// Frames and sends `cmd`, flushing the transport so the frame isn't left in a buffer.
+    pub fn send(&mut self, cmd: &OutgoingCommand) -> Result<(), ClientError> {
+        self.send_no_flush(cmd)?;
+        self.flush()
+    }
+
+    /// Frames and writes `cmd` without flushing the transport, for sending several frames in one
+    /// go. Buffered transports may hold on to the frames until `flush` is called or another
+    /// `send` flushes them along with its own.
+    pub fn send_no_flush(&mut self, cmd: &OutgoingCommand) -> Result<(), ClientError> {
+        Ok(P::write_cmd(&mut self.transport, cmd)?)
+    }
+
+    /// Flushes the transport, sending any frames written by `send_no_flush`.
+    pub fn flush(&mut self) -> Result<(), ClientError> {
+        Ok(self.transport.flush()?)
+    }
+
+    /// Frames and sends a command the crate has no type for, computing the checksums. For
+    /// experimenting with undocumented commands; prefer `send` with a typed command otherwise.
+    pub fn send_command(&mut self, id: u8, payload: &[u8]) -> Result<(), ClientError> {
+        self.send(&OutgoingCommand {
+            id,
+            sub_id: None,
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Writes `bytes` to the transport exactly as given, without framing them. Nothing checks
+    /// that they form a valid frame, so this can also be used to send deliberately broken ones.
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<(), ClientError> {
+        self.transport.write_all(bytes)?;
+        self.flush()
+    }
+
+    /// Blocks until the next frame arrives and returns it decoded.
+    pub fn receive(&mut self) -> Result<IncomingCommand, ClientError> {
+        let mut buf = [0; 64];
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(frame?);
+            }
+            let read = match self.transport.read(&mut buf) {
+                // Part of a frame is buffered, so the rest of it is still to come
+                Err(err) if timed_out(&err) && self.decoder.buffered() > 0 => {
+                    match self.inter_byte_timeout {
+                        Some(timeout) if self.last_byte.elapsed() >= timeout => {
+                            self.decoder.abandon_frame();
+                            return Err(ClientError::PartialFrameTimeout);
+                        }
+                        Some(_) => continue,
+                        None => return Err(err.into()),
+                    }
+                }
+                result => result?,
+            };
+            if read == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.last_byte = Instant::now();
+            self.last_byte_at = SystemTime::now();
+            self.decoder.push(&buf[..read])?;
+        }
+    }
+
+    /// Like `receive`, but also returns when the read that completed the frame returned. Frames
+    /// that arrive together in one read share a timestamp.
+    pub fn receive_timestamped(&mut self) -> Result<Timestamped<IncomingCommand>, ClientError> {
+        let command = self.receive()?;
+        Ok(Timestamped {
+            received_at: self.last_byte_at,
+            command,
+        })
+    }
+
+    /// Asks the board to identify itself, returning its `CMD_BOARD_INFO` and `CMD_BOARD_INFO_3`
+    /// responses. Unrelated and corrupt frames that arrive in the meantime are skipped, and each
+    /// response is waited for like in `wait_until`. A `CMD_ERROR` reply is returned as
+    /// `ClientError::Board`.
+    ///
+    /// The protocol version isn't detected here, since it's fixed by the client's `P` parameter.
+    /// To find out which version a board that is already streaming data uses, call
+    /// `detect_version` on the transport before creating the client.
+    pub fn handshake(&mut self) -> Result<(BoardInfo, BoardInfo3), ClientError> {
+        let info = match self.identify(BoardInfo::ID)? {
+            IncomingCommand::BoardInfo(info) => info,
+            _ => unreachable!("only CMD_BOARD_INFO is waited for"),
+        };
+        let info3 = match self.identify(BoardInfo3::ID)? {
+            IncomingCommand::BoardInfo3(info3) => info3,
+            _ => unreachable!("only CMD_BOARD_INFO_3 is waited for"),
+        };
+        Ok((info, info3))
+    }
+
+    /// Sends an empty request for `id` and waits for the response with the same id
+    fn identify(&mut self, id: u8) -> Result<IncomingCommand, ClientError> {
+        self.send(&OutgoingCommand {
+            id,
+            sub_id: None,
+            payload: Vec::new(),
+        })?;
+        self.wait_for_answer(|frame| frame.id() == id)
+    }
+
+    /// Like `wait_until`, but also stops at a `CMD_ERROR`, returning it as `ClientError::Board`,
+    /// since the board answers a command it can't execute with that instead
+    fn wait_for_answer<F>(&mut self, mut matches: F) -> Result<IncomingCommand, ClientError>
+    where
+        F: FnMut(&IncomingCommand) -> bool,
+    {
+        let answer = self
+            .wait_until(|frame| matches(frame) || matches!(frame, IncomingCommand::CmdError(_)))?;
+        match answer {
+            IncomingCommand::CmdError(err) => Err(ClientError::Board(err)),
+            answer => Ok(answer),
+        }
+    }
+
+    /// Sends `cmd` and waits for the board to answer it, with data or a confirmation depending on
+    /// `OutgoingCommand::response_id`. Commands that aren't known to be answered are waited for as
+    /// confirmations. Skipped frames, timeouts and `CMD_ERROR` are handled like in
+    /// `send_confirmed`.
+    pub fn request(&mut self, cmd: &OutgoingCommand) -> Result<IncomingCommand, ClientError> {
+        let expected = cmd.response_id().unwrap_or(Confirm::ID);
+        self.send(cmd)?;
+        self.wait_for_answer(|frame| match frame {
+            IncomingCommand::Confirm(confirm) => {
+                expected == Confirm::ID && confirm.cmd_id == cmd.id
+            }
+            other => other.id() == expected,
+        })
+    }
+
+    /// Sends `cmd` and waits for the board to confirm it.
+    ///
+    /// Frames other than the confirmation, such as unsolicited telemetry, are skipped. If the
+    /// transport times out or the response timeout passes, the error tells whether anything was
+    /// received at all: `NoResponse` if not, otherwise `UnexpectedResponses` with the ids of the
+    /// skipped frames. If the board answers with `CMD_ERROR` instead, it is returned as
+    /// `ClientError::Board`.
+    pub fn send_confirmed(&mut self, cmd: &OutgoingCommand) -> Result<Confirm, ClientError> {
+        self.send(cmd)?;
+        let confirm = self.wait_for_answer(|frame| match frame {
+            IncomingCommand::Confirm(confirm) => confirm.cmd_id == cmd.id,
+            _ => false,
+        })?;
+        match confirm {
+            IncomingCommand::Confirm(confirm) => Ok(confirm),
+            _ => unreachable!("only confirmations are waited for"),
+        }
+    }
+
+    /// Receives and decodes frames until one `matches`, and returns it. Every other frame, such
+    /// as unsolicited telemetry or confirmations of earlier commands, is passed to `matches` and
+    /// skipped. Frames that are corrupt or can't be decoded are skipped too, and only end the wait
+    /// early if the transport itself fails.
+    ///
+    /// Gives up after the response timeout, which can be raised with `set_response_timeout` for
+    /// slow operations like waiting for the gimbal to reach an angle. Timeouts are reported like
+    /// in `send_confirmed`.
+    pub fn wait_until<F>(&mut self, mut matches: F) -> Result<IncomingCommand, ClientError>
+    where
+        F: FnMut(&IncomingCommand) -> bool,
+    {
+        let deadline = Instant::now() + self.response_timeout;
+        let mut skipped = Vec::new();
+        while Instant::now() < deadline {
+            match self.receive() {
+                Ok(frame) if matches(&frame) => return Ok(frame),
+                Ok(other) => skipped.push(other.id()),
+                Err(ClientError::Parse(ParseError::Io(err))) if timed_out(&err) => break,
+                Err(ClientError::Parse(ParseError::InvalidPayload { id, .. }))
+                | Err(ClientError::Parse(ParseError::TrailingBytes { id, .. })) => skipped.push(id),
+                // The rest of the buffer may still hold the response
+                Err(ClientError::PartialFrameTimeout)
+                | Err(ClientError::Parse(ParseError::InvalidHeaderChecksum))
+                | Err(ClientError::Parse(ParseError::InvalidPayloadChecksum))
+                | Err(ClientError::Parse(ParseError::BufferOverflow(_))) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if skipped.is_empty() {
+            Err(ClientError::NoResponse)
+        } else {
+            Err(ClientError::UnexpectedResponses(skipped))
+        }
+    }
+
+    /// Returns the transport, dropping any bytes that were received but not yet decoded.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Axis, BoardError, GetAngles, GetAnglesResponse};
+
+    /// Replays canned bytes and records what was written. Once the input runs out reads time out,
+    /// like a serial port with nothing more to say.
+    struct Replay {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        /// How much of `output` had been written at the last flush
+        flushed: usize,
+    }
+
+    impl Read for Replay {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(io::ErrorKind::TimedOut.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    fn client(input: Vec<u8>) -> Client<Replay, APIv2> {
+        Client::new(Replay {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+            flushed: 0,
+        })
+    }
+
+    impl Write for Replay {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed = self.output.len();
+            Ok(())
+        }
+    }
+
+    fn response(id: u8, payload: Vec<u8>) -> Vec<u8> {
+        encode_command::<APIv2>(&OutgoingCommand {
+            id,
+            sub_id: None,
+            payload,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn frames_are_stamped_with_when_they_were_read() {
+        let mut input = response(200, vec![1]);
+        input.extend(response(201, vec![2]));
+        let mut client = client(input);
+        let before = SystemTime::now();
+        let first = client.receive_timestamped().unwrap();
+        let second = client.receive_timestamped().unwrap();
+        assert_eq!(first.command.id(), 200);
+        assert_eq!(second.command.id(), 201);
+        assert!(first.received_at >= before);
+        assert!(first.received_at <= SystemTime::now());
+        // Both frames came in the same read
+        assert_eq!(first.received_at, second.received_at);
+    }
+
+    #[test]
+    fn raw_bytes_and_untyped_commands() {
+        let mut client = client(Vec::new());
+        client.send_raw(&[0x24, 0x00, 0xFF]).unwrap();
+        client.send_command(200, &[1, 2]).unwrap();
+
+        let mut sent = vec![0x24, 0x00, 0xFF];
+        sent.extend(response(200, vec![1, 2]));
+        assert_eq!(client.into_inner().output, sent);
+    }
+
+    #[test]
+    fn sends_are_flushed_unless_batched() {
+        let mut client = client(Vec::new());
+        client.send_command(200, &[1]).unwrap();
+        let first = client.transport.output.len();
+        assert_eq!(client.transport.flushed, first);
+
+        client.send_no_flush(&control()).unwrap();
+        client.send_no_flush(&control()).unwrap();
+        assert_eq!(client.transport.flushed, first);
+        client.flush().unwrap();
+        assert_eq!(client.transport.flushed, client.transport.output.len());
+    }
+
+    #[test]
+    fn handshake_skips_unrelated_frames() {
+        let mut board_info = vec![3, 0x01, 0x09, 0, 0, 0, 0];
+        board_info.extend_from_slice(&[0; 15]);
+        let mut board_info3 = vec![0; 73];
+        board_info3[40] = 1; // profile_set_cur
+
+        let mut input = response(200, vec![1, 2, 3]);
+        input.extend(response(BoardInfo::ID, board_info));
+        input.extend(response(BoardInfo3::ID, board_info3));
+        let mut client = client(input);
+
+        let (info, info3) = client.handshake().unwrap();
+        assert_eq!(info.firmware_ver, 2305);
+        assert_eq!(info3.profile_set_cur, 1);
+
+        let mut sent = response(BoardInfo::ID, Vec::new());
+        sent.extend(response(BoardInfo3::ID, Vec::new()));
+        assert_eq!(client.into_inner().output, sent);
+    }
+
+    #[test]
+    fn handshake_reports_errors_and_silence() {
+        let mut input = response(200, vec![1, 2, 3]);
+        input.extend(response(CmdError::ID, vec![5, 0, 0, 0, 0]));
+        match client(input).handshake() {
+            Err(ClientError::Board(err)) => assert_eq!(err.error_code, BoardError::CalibrateBat),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // A board that streams telemetry but never answers is given up on after the timeout
+        struct Streaming(Vec<u8>);
+
+        impl Read for Streaming {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0.rotate_left(len);
+                Ok(len)
+            }
+        }
+
+        impl Write for Streaming {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut streaming = Client::<_, APIv2>::new(Streaming(response(200, vec![1])));
+        streaming.set_response_timeout(Duration::from_millis(20));
+        match streaming.handshake() {
+            Err(ClientError::UnexpectedResponses(ids)) => {
+                assert!(!ids.is_empty());
+                assert!(ids.iter().all(|id| *id == 200));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stalled_frame_is_abandoned() {
+        let frame = response(200, vec![1, 2, 3]);
+        let stalled = &frame[..6];
+
+        let mut abandoning = client(stalled.to_vec());
+        abandoning.set_inter_byte_timeout(Some(Duration::from_millis(10)));
+        assert!(matches!(
+            abandoning.receive(),
+            Err(ClientError::PartialFrameTimeout)
+        ));
+        assert_eq!(abandoning.decoder.buffered(), stalled.len() - 1);
+
+        // Without an inter-byte timeout the transport's timeout is returned and the bytes kept
+        let mut waiting = client(stalled.to_vec());
+        match waiting.receive() {
+            Err(ClientError::Parse(ParseError::Io(err))) => assert!(timed_out(&err)),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(waiting.decoder.buffered(), stalled.len());
+    }
+
+    #[test]
+    fn wait_until_skips_frames_until_one_matches() {
+        let angles = |yaw: u8| {
+            let mut payload = vec![0; 18];
+            payload[12] = yaw;
+            response(GetAnglesResponse::ID, payload)
+        };
+        let mut input = response(Confirm::ID, vec![67]);
+        input.extend(angles(10));
+        input.extend(response(200, vec![1]));
+        input.extend(angles(90));
+        let mut client = client(input);
+        let arrived = |frame: &IncomingCommand| match frame {
+            IncomingCommand::GetAnglesResponse(angles) => angles.axes[Axis::Yaw].imu_angle >= 90,
+            _ => false,
+        };
+        assert_eq!(
+            client.wait_until(arrived).unwrap().id(),
+            GetAnglesResponse::ID
+        );
+
+        match client.wait_until(arrived) {
+            Err(ClientError::NoResponse) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn control() -> OutgoingCommand {
+        OutgoingCommand {
+            id: 67,
+            sub_id: None,
+            payload: vec![0; 15],
+        }
+    }
+
+    #[test]
+    fn confirm_is_found_among_telemetry() {
+        let mut input = response(200, vec![1, 2, 3]);
+        input.extend(response(Confirm::ID, vec![67]));
+        let confirm = client(input).send_confirmed(&control()).unwrap();
+        assert_eq!(
+            confirm,
+            Confirm {
+                cmd_id: 67,
+                data: Vec::new()
+            }
+        );
+    }
+
+    #[test]
+    fn confirm_with_echoed_crc_is_verified() {
+        let sent = control();
+        let frame = response(sent.id, sent.payload.clone());
+        let crc = frame[frame.len() - 2..].to_vec();
+        let confirm = |cmd_id, data: &[u8]| Confirm {
+            cmd_id,
+            data: data.to_vec(),
+        };
+
+        assert!(verify_confirm(&sent, &confirm(67, &crc)));
+        assert!(!verify_confirm(&sent, &confirm(67, &[crc[0] ^ 1, crc[1]])));
+        assert!(!verify_confirm(&sent, &confirm(86, &crc)));
+        assert!(!verify_confirm(&sent, &confirm(67, &[])));
+    }
+
+    #[test]
+    fn missing_confirm_is_told_apart_from_silence() {
+        assert!(matches!(
+            client(Vec::new()).send_confirmed(&control()),
+            Err(ClientError::NoResponse)
+        ));
+
+        // A confirm for a different command doesn't count either
+        let mut input = response(200, vec![1, 2, 3]);
+        input.extend(response(Confirm::ID, vec![86]));
+        match client(input).send_confirmed(&control()) {
+            Err(ClientError::UnexpectedResponses(ids)) => assert_eq!(ids, [200, Confirm::ID]),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn board_errors_end_requests_and_confirmed_sends() {
+        let board_error = || {
+            let mut input = response(200, vec![1, 2, 3]);
+            input.extend(response(CmdError::ID, vec![5, 0, 0, 0, 0]));
+            input
+        };
+        match client(board_error()).send_confirmed(&control()) {
+            Err(ClientError::Board(err)) => assert_eq!(err.error_code, BoardError::CalibrateBat),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let cmd = OutgoingCommand {
+            id: GetAngles::ID,
+            sub_id: None,
+            payload: Vec::new(),
+        };
+        match client(board_error()).request(&cmd) {
+            Err(ClientError::Board(err)) => assert_eq!(err.error_code, BoardError::CalibrateBat),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn request_waits_for_the_response_id() {
+        let mut angles = vec![0; 18];
+        angles[0] = 1;
+        let mut input = response(Confirm::ID, vec![GetAngles::ID]);
+        input.extend(response(GetAnglesResponse::ID, angles));
+        let cmd = OutgoingCommand {
+            id: GetAngles::ID,
+            sub_id: None,
+            payload: Vec::new(),
+        };
+        match client(input).request(&cmd).unwrap() {
+            IncomingCommand::GetAnglesResponse(response) => {
+                assert_eq!(response.axes[Axis::Roll].imu_angle, 1)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    mod loopback {
+        use super::*;
+        use crate::loopback::LoopbackTransport;
+
+        fn unknown(id: u8, payload: &[u8]) -> OutgoingCommand {
+            OutgoingCommand {
+                id,
+                sub_id: None,
+                payload: payload.to_vec(),
+            }
+        }
+
+        #[test]
+        fn sent_frames_come_back() {
+            let mut client = Client::<_, APIv2>::new(LoopbackTransport::new());
+            client.send(&unknown(200, &[1, 2, 3])).unwrap();
+            assert_eq!(
+                client.receive().unwrap(),
+                IncomingCommand::Unknown {
+                    id: 200,
+                    sub_id: None,
+                    payload: vec![1, 2, 3]
+                }
+            );
+            assert!(matches!(
+                client.receive(),
+                Err(ClientError::Parse(ParseError::Io(_)))
+            ));
+        }
+
+        #[test]
+        fn corrupt_frame_is_skipped() {
+            let mut transport = LoopbackTransport::new();
+            transport.inject(&response(200, vec![1]));
+            transport.inject(&response(201, vec![2]));
+            assert!(transport.corrupt(4));
+            let mut client = Client::<_, APIv2>::new(transport);
+            assert!(matches!(
+                client.receive(),
+                Err(ClientError::Parse(ParseError::InvalidPayloadChecksum))
+            ));
+            assert_eq!(client.receive().unwrap().id(), 201);
+        }
+
+        #[test]
+        fn corrupt_telemetry_does_not_end_the_wait() {
+            let mut transport = LoopbackTransport::new();
+            transport.inject(&response(200, vec![1, 2, 3]));
+            assert!(transport.corrupt(5));
+            // Too short to decode as CMD_REALTIME_DATA_3, but intact otherwise
+            transport.inject(&response(23, vec![0; 4]));
+            transport.inject(&response(Confirm::ID, vec![67]));
+            let mut client = Client::<_, APIv2>::new(transport);
+            assert_eq!(client.send_confirmed(&control()).unwrap().cmd_id, 67);
+
+            let mut transport = LoopbackTransport::new();
+            transport.inject(&response(200, vec![1, 2, 3]));
+            assert!(transport.corrupt(5));
+            transport.inject(&response(23, vec![0; 4]));
+            let mut client = Client::<_, APIv2>::new(transport);
+            match client.send_confirmed(&control()) {
+                // The echoed CMD_CONTROL comes last, the corrupt frame's id isn't known
+                Err(ClientError::UnexpectedResponses(ids)) => assert_eq!(ids, [23, 67]),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        #[test]
+        fn echoed_command_is_not_a_confirm() {
+            let mut client = Client::<_, APIv2>::new(LoopbackTransport::new());
+            match client.send_confirmed(&unknown(200, &[])) {
+                Err(ClientError::UnexpectedResponses(ids)) => assert_eq!(ids, [200]),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
@@ -1,30 +1,141 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::io;
-use std::mem::MaybeUninit;
+use std::num::{NonZeroI16, NonZeroI32, NonZeroI8, NonZeroU16, NonZeroU32, NonZeroU8};
+use std::ops::{Index, IndexMut, RangeInclusive};
+use std::time::Duration;
 
 use bitflags::bitflags;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use duplicate::duplicate;
 use impl_trait_for_tuples::impl_for_tuples;
 
+/// A command with a fixed id and payload layout.
+///
+/// This can't be used as `dyn Command`: the id is a constant and the methods are generic over
+/// the reader and writer, and `parse_payload` returns `Self`. Outgoing commands of different
+/// types can be held together as `dyn OutgoingCommandObj` instead.
 pub trait Command
 where
-    // TODO: WTF is with this bound
     Self: Sized,
 {
     const ID: u8;
+    /// Whether this type is sent to the board, received from it, or both
+    const DIRECTION: Direction;
     fn parse_payload<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self>;
     fn write_payload<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()>;
 }
 
-trait Transmit
+/// A command that can be sent to the board. `derive(Command)` implements it unless the command is
+/// marked `#[direction(incoming)]`, so responses can't be sent by mistake:
+///
+/// ```compile_fail
+/// # use simplebgc_api::{BoardInfo, OutgoingCommand};
+/// fn send(info: &BoardInfo) {
+///     OutgoingCommand::from_command(info);
+/// }
+/// ```
+pub trait Outgoing: Command {}
+
+/// A command that can be received from the board. `derive(Command)` implements it unless the
+/// command is marked `#[direction(outgoing)]`.
+pub trait Incoming: Command {}
+
+/// The object-safe part of an `Outgoing` command, implemented for all of them, so commands of
+/// different types can be queued together as `Vec<Box<dyn OutgoingCommandObj>>`.
+pub trait OutgoingCommandObj {
+    fn id(&self) -> u8;
+
+    /// The specification's name for the command, see `command_name`
+    fn name(&self) -> Option<&'static str> {
+        command_name(self.id())
+    }
+
+    /// Writes the payload, like `Command::write_payload`.
+    fn write_payload_dyn(&self, writer: &mut dyn io::Write) -> io::Result<()>;
+
+    /// Serializes the command for sending, like `OutgoingCommand::from_command`. Fails if the
+    /// payload is too long for a frame.
+    fn to_outgoing(&self) -> io::Result<OutgoingCommand> {
+        let mut payload = Vec::new();
+        self.write_payload_dyn(&mut payload)?;
+        OutgoingCommand::with_payload(self.id(), payload)
+    }
+}
+
+impl<C: Outgoing> OutgoingCommandObj for C {
+    fn id(&self) -> u8 {
+        C::ID
+    }
+
+    fn write_payload_dyn(&self, mut writer: &mut dyn io::Write) -> io::Result<()> {
+        self.write_payload(&mut writer)
+    }
+}
+
+pub trait Transmit
 where
     Self: Sized,
 {
+    /// Checks that the value, including any values it's made of, can be sent. This is done when
+    /// reading and writing anyway, but can be called directly to check a command after changing
+    /// it without framing it.
     fn validate(&self) -> io::Result<()> {
         Ok(())
     }
     fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self>;
     fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()>;
+
+    /// Reads a value over `self` instead of returning a new one, so buffers and arrays are reused
+    /// rather than allocated for every frame. Structs, arrays and tuples are read field by field
+    /// in place.
+    ///
+    /// Values are checked like with `from_reader`, but when reading fails part way `self` is
+    /// left with the fields before the failure overwritten and the rest unchanged, so it should
+    /// be read again or discarded.
+    fn read_into<R: ReadBytesExt>(&mut self, reader: &mut R) -> io::Result<()> {
+        *self = Self::from_reader(reader)?;
+        Ok(())
+    }
+
+    /// Reads a value from the start of `bytes`, ignoring any bytes after it.
+    fn from_slice(bytes: &[u8]) -> io::Result<Self> {
+        Self::from_reader(&mut io::Cursor::new(bytes))
+    }
+
+    /// Writes the value into a new buffer.
+    fn to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.to_writer(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    // Arrays go through these so that types with a faster bulk encoding (i.e. u8) can override
+    // them, since the array impl can't be specialized
+    #[doc(hidden)]
+    #[inline]
+    fn array_from_reader<R: ReadBytesExt, const N: usize>(reader: &mut R) -> io::Result<[Self; N]> {
+        let data = (0..N)
+            .map(|_| Self::from_reader(reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        match data.try_into() {
+            Ok(data) => Ok(data),
+            Err(_) => unreachable!("exactly N elements were read"),
+        }
+    }
+    #[doc(hidden)]
+    #[inline]
+    fn slice_read_into<R: ReadBytesExt>(slice: &mut [Self], reader: &mut R) -> io::Result<()> {
+        slice.iter_mut().try_for_each(|elem| elem.read_into(reader))
+    }
+    #[doc(hidden)]
+    #[inline]
+    fn slice_to_writer<W: WriteBytesExt>(slice: &[Self], writer: &mut W) -> io::Result<()> {
+        for elem in slice {
+            elem.to_writer(writer)?;
+        }
+        Ok(())
+    }
 }
 
 // This get's special treatment because it's not generic
@@ -37,6 +148,20 @@ impl Transmit for u8 {
     fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_u8(*self)
     }
+    #[inline]
+    fn array_from_reader<R: ReadBytesExt, const N: usize>(reader: &mut R) -> io::Result<[Self; N]> {
+        let mut data = [0; N];
+        reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+    #[inline]
+    fn slice_read_into<R: ReadBytesExt>(slice: &mut [Self], reader: &mut R) -> io::Result<()> {
+        reader.read_exact(slice)
+    }
+    #[inline]
+    fn slice_to_writer<W: WriteBytesExt>(slice: &[Self], writer: &mut W) -> io::Result<()> {
+        writer.write_all(slice)
+    }
 }
 
 impl Transmit for i8 {
@@ -80,6 +205,7 @@ impl Transmit for bool {
   [i32] [read_i32] [write_i32];
   [u64] [read_u64] [write_u64];
   [i64] [read_i64] [write_i64];
+  [f32] [read_f32] [write_f32];
   [f64] [read_f64] [write_f64];
 )]
 impl Transmit for Num {
@@ -93,38 +219,142 @@ impl Transmit for Num {
     }
 }
 
-// TODO: Rework me when const_generics land
+impl<T: Transmit, const N: usize> Transmit for [T; N] {
+    fn validate(&self) -> io::Result<()> {
+        self.iter().try_for_each(T::validate)
+    }
+    #[inline]
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        T::array_from_reader(reader)
+    }
+    #[inline]
+    fn read_into<R: ReadBytesExt>(&mut self, reader: &mut R) -> io::Result<()> {
+        T::slice_read_into(self, reader)
+    }
+    #[inline]
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        T::slice_to_writer(self, writer)
+    }
+}
+
+/// Non-zero integers are sent like the plain integer, and reading a zero is an error
 #[duplicate(
-  N; [2]; [3]; [5]; [7]; [9]; [12]; [32];
+  NonZero      Int;
+  [NonZeroU8]  [u8];
+  [NonZeroI8]  [i8];
+  [NonZeroU16] [u16];
+  [NonZeroI16] [i16];
+  [NonZeroU32] [u32];
+  [NonZeroI32] [i32];
 )]
-impl<T: Transmit> Transmit for [T; N] {
+impl Transmit for NonZero {
     #[inline]
     fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
-        // TODO: Is there a safe way to do this?
-        // Safety: We initalize the entire array
-        let mut data: Self = unsafe { MaybeUninit::uninit().assume_init() };
-        for elem in data.iter_mut() {
-            *elem = T::from_reader(reader)?;
-        }
-        Ok(data)
+        NonZero::new(Int::from_reader(reader)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "value must not be zero"))
     }
     #[inline]
     fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
-        for elem in self.iter() {
-            elem.to_writer(writer)?;
+        self.get().to_writer(writer)
+    }
+}
+
+/// A 24-bit unsigned integer, sent as three little-endian bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct U24(pub u32);
+
+impl U24 {
+    pub const MAX: u32 = 0xFF_FFFF;
+}
+
+/// A 24-bit signed integer, sent as three little-endian bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct I24(pub i32);
+
+impl I24 {
+    pub const MIN: i32 = -0x80_0000;
+    pub const MAX: i32 = 0x7F_FFFF;
+}
+
+#[duplicate(
+  Int   read_fn    write_fn    Min         Max;
+  [U24] [read_u24] [write_u24] [0]         [U24::MAX];
+  [I24] [read_i24] [write_i24] [I24::MIN] [I24::MAX];
+)]
+impl Transmit for Int {
+    fn validate(&self) -> io::Result<()> {
+        use io::{Error, ErrorKind};
+        if !(Min..=Max).contains(&self.0) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "value does not fit in 24 bits",
+            ));
         }
         Ok(())
     }
+    #[inline]
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        reader.read_fn::<LittleEndian>().map(Self)
+    }
+    #[inline]
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.validate()?;
+        writer.write_fn::<LittleEndian>(self.0)
+    }
+}
+
+/// A duration in whole milliseconds, sent as a `u16`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Millis16(pub u16);
+
+impl From<Millis16> for Duration {
+    fn from(millis: Millis16) -> Self {
+        Duration::from_millis(millis.0.into())
+    }
+}
+
+impl TryFrom<Duration> for Millis16 {
+    type Error = io::Error;
+
+    /// Fails if the duration is longer than `u16::MAX` milliseconds. Sub-millisecond precision is
+    /// truncated.
+    fn try_from(duration: Duration) -> io::Result<Self> {
+        match duration.as_millis().try_into() {
+            Ok(millis) => Ok(Self(millis)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "duration does not fit in 16 bits of milliseconds",
+            )),
+        }
+    }
+}
+
+impl Transmit for Millis16 {
+    #[inline]
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        u16::from_reader(reader).map(Self)
+    }
+    #[inline]
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.to_writer(writer)
+    }
 }
 
 // TODO: Rework me when variadic generics land
 #[impl_for_tuples(5)]
 impl Transmit for Tuple {
+    fn validate(&self) -> io::Result<()> {
+        for_tuples!(#(self.Tuple.validate()?;)*);
+        Ok(())
+    }
     #[inline]
     fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
-        let mut data: Self = unsafe { MaybeUninit::uninit().assume_init() };
-        for_tuples!(#(data.Tuple = Tuple::from_reader(reader)?;)*);
-        Ok(data)
+        Ok(for_tuples!((#(Tuple::from_reader(reader)?),*)))
+    }
+    #[inline]
+    fn read_into<R: ReadBytesExt>(&mut self, reader: &mut R) -> io::Result<()> {
+        for_tuples!(#(self.Tuple.read_into(reader)?;)*);
+        Ok(())
     }
     #[inline]
     fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
@@ -133,6 +363,93 @@ impl Transmit for Tuple {
     }
 }
 
+/// `N` reserved bytes. Their contents are ignored when read and they are written as zeros.
+///
+/// Reserved bytes can sit anywhere in a struct, not just at the end, so gaps in the middle of a
+/// payload are modeled by a `Reserved` field at that position.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reserved<const N: usize>;
+
+impl<const N: usize> Transmit for Reserved<N> {
+    #[inline]
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        <[u8; N]>::from_reader(reader)?;
+        Ok(Self)
+    }
+    #[inline]
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[0; N])
+    }
+}
+
+/// A list sent as a one byte count followed by that many items
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountPrefixed<T>(pub Vec<T>);
+
+// Derived, this would require `T: Default`
+impl<T> Default for CountPrefixed<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: Transmit> Transmit for CountPrefixed<T> {
+    fn validate(&self) -> io::Result<()> {
+        if self.0.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many items for a one byte count",
+            ));
+        }
+        self.0.iter().try_for_each(T::validate)
+    }
+
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let count = reader.read_u8()?;
+        (0..count)
+            .map(|_| T::from_reader(reader))
+            .collect::<io::Result<_>>()
+            .map(Self)
+    }
+
+    fn read_into<R: ReadBytesExt>(&mut self, reader: &mut R) -> io::Result<()> {
+        let count = reader.read_u8()?;
+        self.0.clear();
+        for _ in 0..count {
+            self.0.push(T::from_reader(reader)?);
+        }
+        Ok(())
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.validate()?;
+        writer.write_u8(self.0.len() as u8)?;
+        T::slice_to_writer(&self.0, writer)
+    }
+}
+
+/// Everything left in the payload, for trailing fields that aren't modelled
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Remaining(pub Vec<u8>);
+
+impl Transmit for Remaining {
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Self(bytes))
+    }
+
+    fn read_into<R: ReadBytesExt>(&mut self, reader: &mut R) -> io::Result<()> {
+        self.0.clear();
+        reader.read_to_end(&mut self.0)?;
+        Ok(())
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.0)
+    }
+}
+
 // TODO: Remove when https://github.com/bitflags/bitflags/pull/220 lands
 macro_rules! impl_bflags {
     ($flags:ty, $num:ty) => {
@@ -150,13 +467,40 @@ macro_rules! impl_bflags {
                 self.bits().to_writer(writer)
             }
         }
+
+        impl $flags {
+            /// Every single bit flag, in bit order
+            fn single_flags() -> impl Iterator<Item = Self> {
+                (0..<$num>::BITS).filter_map(|bit| Self::from_bits(1 << bit))
+            }
+
+            /// Names of the set flags, such as `["THREE_AXIS", "ENCODERS"]`, for readable logs
+            pub fn flag_names(&self) -> Vec<String> {
+                Self::single_flags()
+                    .filter(|flag| self.contains(*flag))
+                    .map(|flag| format!("{:?}", flag))
+                    .collect()
+            }
+
+            /// Sets the flags named as in `flag_names`, or returns `None` if a name is unknown.
+            pub fn from_flag_names<'a, I>(names: I) -> Option<Self>
+            where
+                I: IntoIterator<Item = &'a str>,
+            {
+                names.into_iter().try_fold(Self::empty(), |flags, name| {
+                    Self::single_flags()
+                        .find(|flag| format!("{:?}", flag) == name)
+                        .map(|flag| flags | flag)
+                })
+            }
+        }
     };
 }
 
 // TODO: Figure out a better way to modularize these
 
 bitflags! {
-  struct BoardInfoStateFlags: u8 {
+  pub struct BoardInfoStateFlags: u8 {
     /// Internal use only.
     const DEBUG_MODE                = 0b00001;
     /// System is re-configured for frame inversion over middle motor
@@ -173,7 +517,7 @@ bitflags! {
 impl_bflags!(BoardInfoStateFlags, u8);
 
 bitflags! {
-  struct BoardInfoFeatures: u16 {
+  pub struct BoardInfoFeatures: u16 {
     const THREE_AXIS     = 0b000001;
     const BAT_MONITORING = 0b000010;
     const ENCODERS       = 0b000100;
@@ -185,69 +529,121 @@ bitflags! {
 impl_bflags!(BoardInfoFeatures, u16);
 
 bitflags! {
-  struct BoardInfoConnectionFlags: u8 {
+  pub struct BoardInfoConnectionFlags: u8 {
     const CONNECTION_USB = 0b1;
   }
 }
 impl_bflags!(BoardInfoConnectionFlags, u8);
 
-// TODO: Figure out a good way to mark this as incoming
-
-#[derive(Command, Transmit)]
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
 #[id(86)]
+#[direction(incoming)]
 /// CMD_BOARD_INFO – version and board information
-struct BoardInfo {
+pub struct BoardInfo {
     /// Unique Id used to identify each controller in licensing system
-    board_ver: u8,
+    pub board_ver: u8,
     /// Split into decimal digits X.XX.X, for example 2305 means 2.30b5
-    firmware_ver: u16,
-    state_flags1: BoardInfoStateFlags,
-    board_features: BoardInfoFeatures,
-    connection_flag: BoardInfoConnectionFlags,
-    frw_extra_id: u64,
-    _reserved: [u8; 7],
+    pub firmware_ver: u16,
+    pub state_flags1: BoardInfoStateFlags,
+    pub board_features: BoardInfoFeatures,
+    pub connection_flag: BoardInfoConnectionFlags,
+    pub frw_extra_id: u64,
+    _reserved: Reserved<7>,
+}
+
+/// The board's unique id, shown as colon separated hex like `01:23:45:67:89:AB:CD:EF:01`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Transmit)]
+pub struct DeviceId(pub [u8; 9]);
+
+/// The id of the board's microcontroller, shown as colon separated hex
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Transmit)]
+pub struct McuId(pub [u8; 12]);
+
+#[duplicate(Id; [DeviceId]; [McuId])]
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[duplicate(Id; [DeviceId]; [McuId])]
+impl fmt::Debug for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", stringify!(Id), self)
+    }
 }
 
-#[derive(Command, Transmit)]
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
 #[id(20)]
+#[direction(incoming)]
 /// CMD_BOARD_INFO_3 – additional board information
-struct BoardInfo3 {
-    device_id: [u8; 9],
-    mcu_id: [u8; 12],
-    eeprom_size: u64,
+pub struct BoardInfo3 {
+    pub device_id: DeviceId,
+    pub mcu_id: McuId,
+    pub eeprom_size: u64,
     // TODO: Is a tuple a good way to represent this?
-    script_slot_size: [u16; 5],
+    pub script_slot_size: [u16; 5],
     // TODO: Use bitflags for this
-    profile_set_slots: u8,
+    pub profile_set_slots: u8,
     #[range(1..=6)]
-    profile_set_cur: u8,
-    _reserved: [u8; 32],
+    pub profile_set_cur: u8,
+    _reserved: Reserved<32>,
 }
 
-#[derive(Transmit)]
-struct MotorStatus {
+#[derive(Debug, Clone, PartialEq, Transmit)]
+#[transmit(try_new)]
+pub struct MotorStatus {
     #[range(0..=255)]
-    p: u8,
+    pub p: u8,
     #[range(0..=255)]
-    i: u8,
+    pub i: u8,
     #[range(0..=255)]
-    d: u8,
+    pub d: u8,
     #[range(0..=255)]
-    power: u8,
-    invert: bool,
+    pub power: u8,
+    pub invert: bool,
     #[range(0..=255)]
-    poles: u8,
+    pub poles: u8,
+}
+
+impl MotorStatus {
+    pub fn pid(&self) -> (u8, u8, u8) {
+        (self.p, self.i, self.d)
+    }
+
+    /// Sets the PID gains, returning an error if they are out of range like `validate` would.
+    pub fn set_pid(&mut self, p: u8, i: u8, d: u8) -> io::Result<()> {
+        self.p = p;
+        self.i = i;
+        self.d = d;
+        self.validate()
+    }
+}
+
+/// Shows the PID gains as `P/I/D`
+impl fmt::Display for MotorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.p, self.i, self.d)
+    }
 }
 
 // TODO: Clean up RcMode handling!!!
-enum RcModeControl {
+#[derive(Debug, Clone, PartialEq)]
+pub enum RcModeControl {
     Angle,
     Speed,
 }
 
-struct RcMode {
-    mode: RcModeControl,
-    inverted: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub struct RcMode {
+    pub mode: RcModeControl,
+    pub inverted: bool,
 }
 
 impl Transmit for RcMode {
@@ -282,33 +678,196 @@ impl Transmit for RcMode {
     }
 }
 
-#[derive(Transmit)]
-struct RcStatus {
+#[derive(Debug, Clone, PartialEq, Transmit)]
+#[transmit(try_new, validate = Self::check_angles)]
+pub struct RcStatus {
     #[range(-720..=720)]
-    min_angle: i16,
+    pub min_angle: i16,
     #[range(-720..=720)]
-    max_angle: i16,
-    mode: RcMode,
+    pub max_angle: i16,
+    pub mode: RcMode,
     #[range(0..=15)]
-    lpf: u8,
+    pub lpf: u8,
     #[range(0..=255)]
-    speed: u8,
-    // TODO: Read the nodes on this and do some special handling according? Seems difficult
-    #[range(-127..=127)]
-    follow: i8,
+    pub speed: u8,
+    pub follow: RcFollow,
+}
+
+impl RcStatus {
+    fn check_angles(&self) -> io::Result<()> {
+        if self.min_angle > self.max_angle {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "RcStatus.min_angle ({}) is greater than max_angle ({})",
+                    self.min_angle, self.max_angle
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How strongly an axis follows the frame while it is also under RC control, sent as an `i8`.
+///
+/// `0` disables following so the axis only responds to RC. Any other value from `-127` to `127`
+/// is the ratio of follow to RC control, with a negative ratio following in the opposite
+/// direction. `-128` isn't valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RcFollow {
+    Disabled,
+    Ratio(i8),
+}
+
+impl RcFollow {
+    fn to_i8(self) -> i8 {
+        match self {
+            Self::Disabled => 0,
+            Self::Ratio(ratio) => ratio,
+        }
+    }
+}
+
+impl Transmit for RcFollow {
+    fn validate(&self) -> io::Result<()> {
+        match self {
+            Self::Ratio(0) | Self::Ratio(i8::MIN) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "follow ratio must be from -127 to 127 and not zero",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        match reader.read_i8()? {
+            0 => Ok(Self::Disabled),
+            i8::MIN => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "follow ratio out of range",
+            )),
+            ratio => Ok(Self::Ratio(ratio)),
+        }
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_i8(self.to_i8())
+    }
+}
+
+/// An input that can drive an axis or function, sent as a byte with the input type in bits 5-7
+/// and the input's number, starting from 1, in bits 0-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RcSource {
+    /// Nothing is assigned
+    None,
+    /// A PWM input: 1 is RC_ROLL, 2 RC_PITCH, 3 EXT_FC_ROLL, 4 EXT_FC_PITCH and 5 RC_YAW
+    Pwm(u8),
+    /// An analog input, ADC1 to ADC3
+    Analog(u8),
+    /// A channel of a serial receiver such as SBUS or Spektrum
+    Serial(u8),
+    /// A virtual channel set over the API
+    Virtual(u8),
+}
+
+impl RcSource {
+    /// The numbers each input type has, in the order of the type bits
+    const NUMBERS: [RangeInclusive<u8>; 4] = [1..=5, 1..=3, 1..=18, 1..=31];
+
+    fn type_and_number(self) -> Option<(u8, u8)> {
+        match self {
+            Self::None => None,
+            Self::Pwm(n) => Some((0, n)),
+            Self::Analog(n) => Some((1, n)),
+            Self::Serial(n) => Some((2, n)),
+            Self::Virtual(n) => Some((3, n)),
+        }
+    }
+}
+
+impl Transmit for RcSource {
+    fn validate(&self) -> io::Result<()> {
+        match self.type_and_number() {
+            Some((ty, n)) if !Self::NUMBERS[ty as usize].contains(&n) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no RC input {:?}", self),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let byte = reader.read_u8()?;
+        let n = byte & 0b0001_1111;
+        let source = match byte >> 5 {
+            _ if byte == 0 => Self::None,
+            0 => Self::Pwm(n),
+            1 => Self::Analog(n),
+            2 => Self::Serial(n),
+            3 => Self::Virtual(n),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown RC input type",
+                ))
+            }
+        };
+        source.validate()?;
+        Ok(source)
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.validate()?;
+        let byte = self.type_and_number().map_or(0, |(ty, n)| ty << 5 | n);
+        writer.write_u8(byte)
+    }
+}
+
+/// Which input drives each axis and function, part of the profile parameters
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct RcMapping {
+    pub roll: RcSource,
+    pub pitch: RcSource,
+    pub yaw: RcSource,
+    /// Triggers commands, such as switching profiles, by its position
+    pub cmd: RcSource,
+    /// Roll from an external flight controller
+    pub fc_roll: RcSource,
+    /// Pitch from an external flight controller
+    pub fc_pitch: RcSource,
+}
+
+impl RcMapping {
+    /// The input driving `axis`
+    pub fn axis(&self, axis: Axis) -> RcSource {
+        match axis {
+            Axis::Roll => self.roll,
+            Axis::Pitch => self.pitch,
+            Axis::Yaw => self.yaw,
+        }
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, source: RcSource) {
+        match axis {
+            Axis::Roll => self.roll = source,
+            Axis::Pitch => self.pitch = source,
+            Axis::Yaw => self.yaw = source,
+        }
+    }
 }
 
-#[derive(Transmit)]
+#[derive(Debug, Clone, PartialEq, Transmit)]
 #[repr(u8)]
-enum PWMFrequency {
+pub enum PWMFrequency {
     Low = 0,
     High = 1,
     Pitch = 2,
 }
 
-#[derive(Transmit)]
+#[derive(Debug, Clone, PartialEq, Transmit)]
 #[repr(u8)]
-enum BaudRate {
+pub enum BaudRate {
     Baud115200 = 0,
     Baud57600 = 1,
     Baud38400 = 2,
@@ -317,21 +876,2695 @@ enum BaudRate {
     Baud256000 = 5,
 }
 
-#[derive(Command, Transmit)]
-#[id(21)]
-struct ReadParams3 {
-    #[range(0..=4, 255..=255)]
-    profile_id: u8,
-    axis: [MotorStatus; 3],
-    #[range(0..=255)]
-    acc_limiter_all: u8,
-    ext_fc_gain: [i8; 2],
-    rc_status: [RcStatus; 3],
-    #[range(0..=255)]
-    gyro_thrust: u8,
-    use_model: bool,
-    pwm_freq: PWMFrequency,
-    // TODO: Is this a typo?
-    serial_spped: BaudRate,
-    // TODO: Ugh, I need ranges on arrays. Probably use iter_range()
+/// One of the five parameter profiles, or whichever is currently active. Sent as the profile's
+/// index, with 255 meaning the current profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileId {
+    /// A profile from 0 to 4
+    Index(u8),
+    Current,
+}
+
+impl ProfileId {
+    pub const MAX_INDEX: u8 = 4;
+    const CURRENT: u8 = 255;
+}
+
+impl Transmit for ProfileId {
+    fn validate(&self) -> io::Result<()> {
+        match self {
+            Self::Index(index) if *index > Self::MAX_INDEX => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "profile index must be from 0 to 4",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let profile = match reader.read_u8()? {
+            Self::CURRENT => Self::Current,
+            index => Self::Index(index),
+        };
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.validate()?;
+        match self {
+            Self::Index(index) => writer.write_u8(*index),
+            Self::Current => writer.write_u8(Self::CURRENT),
+        }
+    }
+}
+
+/// The gimbal axes, in the order per-axis arrays list them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    Roll = 0,
+    Pitch = 1,
+    Yaw = 2,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::Roll, Axis::Pitch, Axis::Yaw];
+
+    /// Position of the axis in per-axis arrays
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Pairs each value of a per-axis array with its axis.
+    pub fn zip<T>(values: &[T; 3]) -> impl Iterator<Item = (Axis, &T)> {
+        Self::ALL.iter().copied().zip(values.iter())
+    }
+}
+
+/// A value for each axis, indexed by `Axis` and sent like `[T; 3]` in roll, pitch, yaw order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PerAxis<T>(pub [T; 3]);
+
+impl<T> PerAxis<T> {
+    /// Each value along with its axis
+    pub fn iter(&self) -> impl Iterator<Item = (Axis, &T)> {
+        Axis::zip(&self.0)
+    }
+}
+
+impl<T> From<[T; 3]> for PerAxis<T> {
+    fn from(values: [T; 3]) -> Self {
+        Self(values)
+    }
+}
+
+impl<T> Index<Axis> for PerAxis<T> {
+    type Output = T;
+
+    fn index(&self, axis: Axis) -> &T {
+        &self.0[axis.index()]
+    }
+}
+
+impl<T> IndexMut<Axis> for PerAxis<T> {
+    fn index_mut(&mut self, axis: Axis) -> &mut T {
+        &mut self.0[axis.index()]
+    }
+}
+
+impl<T: Transmit> Transmit for PerAxis<T> {
+    fn validate(&self) -> io::Result<()> {
+        self.0.validate()
+    }
+    #[inline]
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        Transmit::from_reader(reader).map(Self)
+    }
+    #[inline]
+    fn read_into<R: ReadBytesExt>(&mut self, reader: &mut R) -> io::Result<()> {
+        self.0.read_into(reader)
+    }
+    #[inline]
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.to_writer(writer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(21)]
+#[direction(incoming)]
+pub struct ReadParams3 {
+    pub profile_id: ProfileId,
+    pub axis: PerAxis<MotorStatus>,
+    #[range(0..=255)]
+    pub acc_limiter_all: u8,
+    #[range(-100..=100)]
+    pub ext_fc_gain: [i8; 2],
+    pub rc_status: PerAxis<RcStatus>,
+    #[range(0..=255)]
+    pub gyro_thrust: u8,
+    pub use_model: bool,
+    pub pwm_freq: PWMFrequency,
+    pub serial_speed: BaudRate,
+    #[range(-127..=127)]
+    pub rc_trim: [i8; 3],
+    pub rc_deadband: u8,
+    pub rc_expo_rate: u8,
+    pub rc_virt_mode: u8,
+    pub rc_map: RcMapping,
+    /// How much of each flight controller input is mixed into roll and pitch
+    pub rc_mix_fc: [u8; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(22)]
+#[direction(outgoing)]
+/// CMD_WRITE_PARAMS_3 – writes the parameters of a profile. Usually built from a `ReadParams3`
+/// with the fields to change modified.
+pub struct WriteParams3 {
+    pub profile_id: ProfileId,
+    pub axis: PerAxis<MotorStatus>,
+    #[range(0..=255)]
+    pub acc_limiter_all: u8,
+    #[range(-100..=100)]
+    pub ext_fc_gain: [i8; 2],
+    pub rc_status: PerAxis<RcStatus>,
+    #[range(0..=255)]
+    pub gyro_thrust: u8,
+    pub use_model: bool,
+    pub pwm_freq: PWMFrequency,
+    pub serial_speed: BaudRate,
+    #[range(-127..=127)]
+    pub rc_trim: [i8; 3],
+    pub rc_deadband: u8,
+    pub rc_expo_rate: u8,
+    pub rc_virt_mode: u8,
+    pub rc_map: RcMapping,
+    /// How much of each flight controller input is mixed into roll and pitch
+    pub rc_mix_fc: [u8; 2],
+}
+
+#[duplicate(Params; [ReadParams3]; [WriteParams3])]
+impl Params {
+    pub fn axis(&self, axis: Axis) -> &MotorStatus {
+        &self.axis[axis]
+    }
+
+    pub fn axis_mut(&mut self, axis: Axis) -> &mut MotorStatus {
+        &mut self.axis[axis]
+    }
+
+    /// The motor settings of each axis, along with the axis
+    pub fn axes(&self) -> impl Iterator<Item = (Axis, &MotorStatus)> {
+        self.axis.iter()
+    }
+
+    pub fn rc(&self, axis: Axis) -> &RcStatus {
+        &self.rc_status[axis]
+    }
+
+    pub fn rc_mut(&mut self, axis: Axis) -> &mut RcStatus {
+        &mut self.rc_status[axis]
+    }
+
+    /// The name, old value and new value of every field that differs between `self` and
+    /// `other`, with the values formatted with `Debug`. Arrays are compared and listed as a
+    /// whole.
+    pub fn diff(&self, other: &Self) -> Vec<(&'static str, String, String)> {
+        let mut changes = Vec::new();
+        macro_rules! compare {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != other.$field {
+                        changes.push((
+                            stringify!($field),
+                            format!("{:?}", self.$field),
+                            format!("{:?}", other.$field),
+                        ));
+                    }
+                )*
+            };
+        }
+        compare!(
+            profile_id,
+            axis,
+            acc_limiter_all,
+            ext_fc_gain,
+            rc_status,
+            gyro_thrust,
+            use_model,
+            pwm_freq,
+            serial_speed,
+            rc_trim,
+            rc_deadband,
+            rc_expo_rate,
+            rc_virt_mode,
+            rc_map,
+            rc_mix_fc
+        );
+        changes
+    }
+}
+
+impl GetAnglesResponse {
+    pub fn axis(&self, axis: Axis) -> &AxisAngles {
+        &self.axes[axis]
+    }
+}
+
+impl From<&ReadParams3> for WriteParams3 {
+    fn from(params: &ReadParams3) -> Self {
+        Self {
+            profile_id: params.profile_id,
+            axis: params.axis.clone(),
+            acc_limiter_all: params.acc_limiter_all,
+            ext_fc_gain: params.ext_fc_gain,
+            rc_status: params.rc_status.clone(),
+            gyro_thrust: params.gyro_thrust,
+            use_model: params.use_model,
+            pwm_freq: params.pwm_freq.clone(),
+            serial_speed: params.serial_speed.clone(),
+            rc_trim: params.rc_trim,
+            rc_deadband: params.rc_deadband,
+            rc_expo_rate: params.rc_expo_rate,
+            rc_virt_mode: params.rc_virt_mode,
+            rc_map: params.rc_map.clone(),
+            rc_mix_fc: params.rc_mix_fc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(33)]
+#[direction(incoming)]
+/// CMD_READ_PARAMS_EXT – filter, encoder and motor parameters of a profile, beyond those in
+/// `ReadParams3`. Arrays of three hold a value for each axis.
+pub struct ReadParamsExt {
+    pub profile_id: ProfileId,
+    /// Center frequencies of each axis's three notch filters in Hz
+    pub notch_freq: [[u8; 3]; 3],
+    /// Widths of each axis's three notch filters in Hz
+    pub notch_width: [[u8; 3]; 3],
+    /// Low-pass filter frequencies in Hz
+    pub lpf_freq: [u16; 3],
+    /// Which of the notch and low-pass filters are enabled, one bit for each
+    pub filters_en: [u8; 3],
+    /// In units of 0.02197265625 degrees
+    pub encoder_offset: [i16; 3],
+    /// In units of 0.02197265625 degrees
+    pub encoder_fld_offset: [i16; 3],
+    pub encoder_manual_set_time: [u8; 3],
+    pub motor_heating_factor: [u8; 3],
+    pub motor_cooling_factor: [u8; 3],
+    pub _reserved: Reserved<2>,
+    /// How closely the frame is followed inside the follow deadband
+    pub follow_inside_deadband: u8,
+    pub motor_mag_link: [u8; 3],
+    pub motor_gearing: [u16; 3],
+    pub encoder_limit_min: [i8; 3],
+    pub encoder_limit_max: [i8; 3],
+    /// Gains of each axis's three notch filters
+    pub notch_gain: [[i8; 3]; 3],
+    pub beeper_volume: u8,
+    pub encoder_cfg: [u8; 3],
+    pub encoder_cfg_info: [u8; 3],
+    /// Fields this crate doesn't model yet, kept so the parameters can be written back unchanged
+    pub rest: Remaining,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(62)]
+#[direction(incoming)]
+/// CMD_READ_PARAMS_EXT2 – MAVLink, auto-tuning and frame limit parameters of a profile. Arrays of
+/// three hold a value for each axis.
+pub struct ReadParamsExt2 {
+    pub profile_id: ProfileId,
+    /// Serial port used for MAVLink, 0 when disabled
+    pub mav_src: u8,
+    pub mav_sys_id: u8,
+    pub mav_comp_id: u8,
+    pub mav_cfg_flags: u8,
+    pub _reserved1: Reserved<4>,
+    pub motor_mag_link_fine: [u16; 3],
+    /// In units of degrees/sec²
+    pub acc_limiter: [u16; 3],
+    pub pid_gain: [u8; 3],
+    pub frame_imu_lpf_freq: u8,
+    pub auto_pid_cfg: u8,
+    pub auto_pid_gain: u8,
+    /// In units of 0.02197265625 degrees
+    pub frame_cam_angle_min: [i16; 3],
+    /// In units of 0.02197265625 degrees
+    pub frame_cam_angle_max: [i16; 3],
+    pub general_flags1: u16,
+    pub profile_flags1: u16,
+    pub spektrum_mode: u8,
+    pub order_of_axes: u8,
+    pub euler_order: u8,
+    pub cur_imu: u8,
+    pub cur_profile_id: u8,
+    /// Fields this crate doesn't model yet, kept so the parameters can be written back unchanged
+    pub rest: Remaining,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(104)]
+#[direction(incoming)]
+/// CMD_READ_PARAMS_EXT3 – further parameters of a profile. Its fields aren't modelled yet, so
+/// they are kept as raw bytes.
+pub struct ReadParamsExt3 {
+    pub profile_id: ProfileId,
+    pub rest: Remaining,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Transmit)]
+#[repr(u8)]
+pub enum ControlMode {
+    /// The axis isn't affected by this command, so it carries on with whatever it was doing
+    /// before, holding its position or following the previous command. This is not the same as a
+    /// speed of zero: to stop an axis, use `Speed` with a speed of 0.
+    NoControl = 0,
+    /// Rotate at the given speed
+    Speed = 1,
+    /// Move to the given angle
+    Angle = 2,
+    /// Rotate at the given speed, correcting towards the given angle
+    SpeedAngle = 3,
+    /// The speed and angle are interpreted as an RC signal
+    Rc = 4,
+    /// Move to the given angle relative to the frame
+    AngleRelFrame = 5,
+    /// Like `Rc` but with a higher resolution
+    RcHighRes = 6,
+}
+
+/// An angular speed, sent as an `i16` in units of 0.1220740379 degrees/sec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Transmit)]
+pub struct GimbalSpeed(pub i16);
+
+impl GimbalSpeed {
+    /// Degrees/sec per unit
+    pub const UNIT: f64 = 0.1220740379;
+
+    pub fn to_deg_per_sec(self) -> f64 {
+        f64::from(self.0) * Self::UNIT
+    }
+
+    /// The nearest speed to `deg_per_sec`, or `None` if it is out of range or not a number.
+    pub fn from_deg_per_sec(deg_per_sec: f64) -> Option<Self> {
+        let units = (deg_per_sec / Self::UNIT).round();
+        if units >= f64::from(i16::MIN) && units <= f64::from(i16::MAX) {
+            Some(Self(units as i16))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Transmit)]
+pub struct ControlAxis {
+    pub speed: GimbalSpeed,
+    /// In units of 0.02197265625 degrees
+    pub angle: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(67)]
+#[direction(outgoing)]
+/// CMD_CONTROL – controls the gimbal's movement
+pub struct Control {
+    /// Mode of the roll, pitch and yaw axes
+    pub mode: [ControlMode; 3],
+    pub axes: [ControlAxis; 3],
+}
+
+impl Control {
+    /// Controls only `axis`, leaving the others in `NoControl` so they are unaffected.
+    pub fn axis_only(axis: Axis, mode: ControlMode, control: ControlAxis) -> Self {
+        let mut cmd = Self {
+            mode: [ControlMode::NoControl; 3],
+            axes: [ControlAxis {
+                speed: GimbalSpeed(0),
+                angle: 0,
+            }; 3],
+        };
+        cmd.mode[axis.index()] = mode;
+        cmd.axes[axis.index()] = control;
+        cmd
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Command, Transmit)]
+#[id(73)]
+#[direction(outgoing)]
+/// CMD_GET_ANGLES – requests the angles of each axis
+pub struct GetAngles;
+
+#[derive(Debug, Clone, Copy, PartialEq, Transmit)]
+pub struct AxisAngles {
+    /// In units of 0.02197265625 degrees
+    pub imu_angle: i16,
+    /// In units of 0.02197265625 degrees
+    pub target_angle: i16,
+    pub target_speed: GimbalSpeed,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(73)]
+#[direction(incoming)]
+/// CMD_GET_ANGLES – angles of the roll, pitch and yaw axes
+pub struct GetAnglesResponse {
+    pub axes: PerAxis<AxisAngles>,
+}
+
+bitflags! {
+  /// Errors reported in `RealtimeData3::system_error`
+  pub struct SystemErrorFlags: u16 {
+    const NO_SENSOR      = 1 << 0;
+    const CALIB_ACC      = 1 << 1;
+    const SET_POWER      = 1 << 2;
+    const CALIB_POLES    = 1 << 3;
+    /// Motor overheating or overcurrent protection tripped
+    const PROTECTION     = 1 << 4;
+    const SERIAL         = 1 << 5;
+    /// Battery voltage below the warning level
+    const LOW_BAT1       = 1 << 6;
+    /// Battery voltage below the critical level, motors are turned off
+    const LOW_BAT2       = 1 << 7;
+    /// GUI version is too old for the firmware
+    const GUI_VERSION    = 1 << 8;
+    /// The motors skipped steps
+    const MISS_STEPS     = 1 << 9;
+    const SYSTEM         = 1 << 10;
+    const EMERGENCY_STOP = 1 << 11;
+  }
+}
+impl_bflags!(SystemErrorFlags, u16);
+
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct SensorData {
+    pub acc: i16,
+    pub gyro: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(23)]
+#[direction(incoming)]
+/// CMD_REALTIME_DATA_3 – sensor readings and the state of the controller
+pub struct RealtimeData3 {
+    pub sensors: [SensorData; 3],
+    pub serial_err_cnt: u16,
+    pub system_error: SystemErrorFlags,
+    /// Details of `system_error`, depending on which error it is
+    pub system_sub_error: u8,
+    _reserved: Reserved<3>,
+    /// RC input for roll, pitch and yaw
+    pub rc: [i16; 3],
+    pub rc_cmd: i16,
+    /// Roll and pitch from an external flight controller
+    pub ext_fc: [i16; 2],
+    pub imu_angle: PerAxis<i16>,
+    pub frame_imu_angle: PerAxis<i16>,
+    pub target_angle: PerAxis<i16>,
+    /// Main loop time in microseconds
+    pub cycle_time: u16,
+    pub i2c_error_count: u16,
+    /// Superseded by `system_error`
+    pub error_code: u8,
+    /// Battery voltage in units of 0.01V
+    #[transmit(scale = 0.01)]
+    pub bat_level: u16,
+    pub rt_data_flags: u8,
+    pub cur_imu: u8,
+    pub cur_profile: u8,
+    pub motor_power: [u8; 3],
+}
+
+impl RealtimeData3 {
+    /// Battery voltage in volts
+    pub fn battery_volts(&self) -> f32 {
+        self.bat_level_scaled() as f32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(25)]
+#[direction(incoming)]
+/// CMD_REALTIME_DATA_4 – `CMD_REALTIME_DATA_3` extended with more sensor readings
+pub struct RealtimeData4 {
+    pub base: RealtimeData3,
+    pub frame_cam_angle: PerAxis<i16>,
+    _reserved1: Reserved<1>,
+    pub balance_error: [i16; 3],
+    /// Current drawn in mA
+    #[transmit(scale = 0.001)]
+    pub current: u16,
+    pub mag_data: [i16; 3],
+    /// Temperature in degrees Celsius
+    pub imu_temperature: i8,
+    pub frame_imu_temperature: i8,
+    pub imu_g_err: u8,
+    pub imu_h_err: u8,
+    pub motor_out: [i16; 3],
+    pub calib_mode: u8,
+    pub can_imu_ext_sens_err: u8,
+    _reserved2: Reserved<28>,
+}
+
+impl RealtimeData4 {
+    /// Battery voltage in volts
+    pub fn battery_volts(&self) -> f32 {
+        self.base.battery_volts()
+    }
+
+    /// Current drawn in amps. Only `CMD_REALTIME_DATA_4` reports it.
+    pub fn current_amps(&self) -> f32 {
+        self.current_scaled() as f32
+    }
+
+    /// Power drawn from the battery in watts
+    pub fn power_watts(&self) -> f32 {
+        self.battery_volts() * self.current_amps()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(85)]
+#[direction(outgoing)]
+/// CMD_DATA_STREAM_INTERVAL – asks the board to send a command on its own at a fixed interval
+pub struct DataStreamInterval {
+    /// The command to send, e.g. `CMD_REALTIME_DATA_3`
+    pub cmd_id: u8,
+    /// Time between frames, or 0 to stop sending them
+    pub interval_ms: Millis16,
+    /// Meaning depends on the command being sent
+    pub config: [u8; 8],
+    pub _reserved: Reserved<10>,
+}
+
+/// Defines the fields `CMD_REALTIME_DATA_CUSTOM` can report. Each field's flag, builder method and
+/// decoded value all come from the same line, so requests and responses always agree. Fields must
+/// be listed in bit order since that's the order the board sends them in.
+macro_rules! realtime_data_custom {
+    ($( $(#[doc = $doc:tt])* $flag:ident = $bit:expr => $field:ident: $ty:ty; )*) => {
+        bitflags! {
+            /// The fields requested with `CMD_REALTIME_DATA_CUSTOM`
+            pub struct RealtimeDataCustomFlags: u32 {
+                $( $(#[doc = $doc])* const $flag = 1 << $bit; )*
+            }
+        }
+        impl_bflags!(RealtimeDataCustomFlags, u32);
+
+        #[derive(Debug, Clone, PartialEq, Command, Transmit)]
+        #[id(88)]
+        #[direction(outgoing)]
+        /// CMD_REALTIME_DATA_CUSTOM – requests a selection of realtime data. Build it by chaining
+        /// the methods for the wanted fields, e.g. `RealtimeDataCustom::new().imu_angles()`.
+        ///
+        /// The protocol's table of custom data has no bit for the power of the three main
+        /// motors, so there is no `motor_power` field. Use `RealtimeData3::motor_power` instead.
+        pub struct RealtimeDataCustom {
+            pub flags: RealtimeDataCustomFlags,
+            pub _reserved: Reserved<6>,
+        }
+
+        impl RealtimeDataCustom {
+            /// A request for no fields besides the timestamp
+            pub fn new() -> Self {
+                Self {
+                    flags: RealtimeDataCustomFlags::empty(),
+                    _reserved: Reserved,
+                }
+            }
+
+            $(
+                $(#[doc = $doc])*
+                pub fn $field(mut self) -> Self {
+                    self.flags |= RealtimeDataCustomFlags::$flag;
+                    self
+                }
+            )*
+        }
+
+        impl Default for RealtimeDataCustom {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        /// The response to `CMD_REALTIME_DATA_CUSTOM`. Fields that weren't requested are `None`.
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct RealtimeDataCustomResponse {
+            pub timestamp_ms: u16,
+            $( $(#[doc = $doc])* pub $field: Option<$ty>, )*
+        }
+
+        impl RealtimeDataCustomResponse {
+            /// Decodes a response to a request for `flags`. The response doesn't say which fields
+            /// it contains, so this can't be done without the flags that were requested.
+            pub fn parse(flags: RealtimeDataCustomFlags, payload: &[u8]) -> io::Result<Self> {
+                let mut reader = payload;
+                let mut data = Self {
+                    timestamp_ms: u16::from_reader(&mut reader)?,
+                    ..Self::default()
+                };
+                $(
+                    if flags.contains(RealtimeDataCustomFlags::$flag) {
+                        data.$field = Some(<$ty>::from_reader(&mut reader)?);
+                    }
+                )*
+                Ok(data)
+            }
+        }
+    };
+}
+
+realtime_data_custom! {
+    /// IMU angles in units of 0.02197265625 degrees
+    IMU_ANGLES = 0 => imu_angles: [i16; 3];
+    /// Target angles in units of 0.02197265625 degrees
+    TARGET_ANGLES = 1 => target_angles: [i16; 3];
+    /// Target speeds
+    TARGET_SPEED = 2 => target_speed: [GimbalSpeed; 3];
+    /// Angles of the frame IMU relative to the camera IMU in units of 0.02197265625 degrees
+    FRAME_CAM_ANGLE = 3 => frame_cam_angle: [i16; 3];
+    /// Gyroscope sensor data
+    GYRO_DATA = 4 => gyro_data: [i16; 3];
+    /// RC inputs in the -16384..=16384 range
+    RC_DATA = 5 => rc_data: [i16; 6];
+    /// Z and H vectors in the camera IMU's frame
+    Z_VECTOR_H_VECTOR = 6 => z_vector_h_vector: [f32; 6];
+    /// RC channels in the -16384..=16384 range
+    RC_CHANNELS = 7 => rc_channels: [i16; 18];
+    /// Accelerometer sensor data
+    ACC_DATA = 8 => acc_data: [i16; 3];
+    /// Raw encoder readings
+    ENCODER_RAW24 = 11 => encoder_raw24: [I24; 3];
+    /// IMU angles in radians
+    IMU_ANGLES_RAD = 12 => imu_angles_rad: [f32; 3];
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(96)]
+#[direction(outgoing)]
+/// CMD_CAN_DEVICE_SCAN – asks the board to look for devices on its CAN bus
+pub struct CanDeviceScan;
+
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct CanDevice {
+    /// Unique id of the device's hardware
+    pub uid: [u8; 6],
+    /// Address on the bus
+    pub id: u8,
+    pub device_type: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(96)]
+#[direction(incoming)]
+/// CMD_CAN_DEVICE_SCAN – the devices found on the CAN bus
+pub struct CanDeviceScanResponse {
+    pub devices: CountPrefixed<CanDevice>,
+}
+
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct CanDriverState {
+    /// Address of the driver on the bus
+    pub id: u8,
+    pub state_flags: u8,
+    /// Temperature in degrees Celsius
+    pub temperature: i8,
+    /// Non-zero if the driver has an error, with the bits identifying it
+    pub error_flags: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(101)]
+#[direction(incoming)]
+/// CMD_REALTIME_DATA_CAN_DRV – the state of each CAN motor driver
+pub struct RealtimeDataCanDrv {
+    pub drivers: CountPrefixed<CanDriverState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(93)]
+#[direction(outgoing)]
+#[transmit(try_new)]
+/// CMD_CALIB_COGGING – starts cogging calibration of the chosen axes
+pub struct CalibCogging {
+    /// Whether to calibrate each axis
+    pub axes: [bool; 3],
+    /// Number of passes to average over, more is slower but smoother
+    #[range(1..=255)]
+    pub iterations: u8,
+    pub _reserved: Reserved<4>,
+}
+
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct CoggingAxisInfo {
+    pub calibrated: bool,
+    /// Correction for each step of the motor's rotation, empty if not calibrated
+    pub curve: CountPrefixed<i16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(92)]
+#[direction(incoming)]
+/// CMD_COGGING_CALIB_INFO – the result of cogging calibration, sent in response to an empty
+/// request with the same id
+pub struct CoggingCalibInfo {
+    pub axes: [CoggingAxisInfo; 3],
+}
+
+/// What a correction curve corrects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Transmit)]
+#[repr(u8)]
+pub enum CorrCurveType {
+    /// Cogging torque of the motor
+    Cogging = 0,
+    /// Offset of the motor's angle sensor
+    Offset = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Command)]
+#[id(113)]
+#[direction(outgoing)]
+/// CMD_SEND_CORR_CURVE – uploads a correction curve for one motor, such as one edited after
+/// `CMD_COGGING_CALIB_INFO`
+pub struct SendCorrCurve {
+    pub axis: Axis,
+    pub curve_type: CorrCurveType,
+    /// Correction for each step of the motor's rotation, at most `MAX_POINTS`
+    pub points: CountPrefixed<i16>,
+}
+
+impl SendCorrCurve {
+    /// The most points a curve can have
+    pub const MAX_POINTS: usize = 128;
+}
+
+impl Transmit for SendCorrCurve {
+    fn validate(&self) -> io::Result<()> {
+        if self.points.0.len() > Self::MAX_POINTS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "correction curve has {} points, at most {} are allowed",
+                    self.points.0.len(),
+                    Self::MAX_POINTS
+                ),
+            ));
+        }
+        self.points.validate()
+    }
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let axis = Axis::ALL
+            .get(usize::from(reader.read_u8()?))
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid axis"))?;
+        let value = Self {
+            axis,
+            curve_type: CorrCurveType::from_reader(reader)?,
+            points: CountPrefixed::from_reader(reader)?,
+        };
+        value.validate()?;
+        Ok(value)
+    }
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.validate()?;
+        writer.write_u8(self.axis.index() as u8)?;
+        self.curve_type.to_writer(writer)?;
+        self.points.to_writer(writer)
+    }
+}
+
+/// Which of the board's IMUs `CMD_SELECT_IMU_3` selects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Transmit)]
+#[repr(u8)]
+pub enum Imu {
+    /// The IMU mounted with the camera
+    Main = 1,
+    /// The IMU mounted on the frame, below the yaw motor
+    Frame = 2,
+}
+
+/// What the board does with the IMU chosen by `CMD_SELECT_IMU_3`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Transmit)]
+#[repr(u8)]
+pub enum SelectImuAction {
+    /// Only select the IMU
+    Select = 0,
+    /// Select the IMU and make it the one selected after the board restarts
+    SelectAndSave = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(24)]
+#[direction(outgoing)]
+/// CMD_SELECT_IMU_3 – chooses which IMU later commands apply to. The board confirms it with
+/// `CMD_CONFIRM`.
+///
+/// The selection is state on the board, not part of each command: calibration commands such as
+/// `CMD_CALIB_ACC` and `CMD_CALIB_GYRO`, and the IMU data in `CMD_REALTIME_DATA_3`, all apply to
+/// whichever IMU was selected last. To calibrate the frame IMU, select `Imu::Frame`, calibrate,
+/// then select `Imu::Main` again, otherwise the next calibration meant for the main IMU goes to
+/// the frame IMU. `RealtimeData3::cur_imu` and `ReadParamsExt2::cur_imu` report the current
+/// selection.
+pub struct SelectImu3 {
+    pub imu: Imu,
+    pub action: SelectImuAction,
+}
+
+macro_rules! adj_vars {
+    ($( $var:ident = $id:literal, $name:literal, $range:expr; )*) => {
+        /// A variable that can be changed while the gimbal is running, either from a
+        /// `CMD_SET_ADJ_VARS_VAL` or from an RC input assigned to it
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Transmit)]
+        #[repr(u8)]
+        pub enum AdjVar {
+            $( $var = $id, )*
+        }
+
+        impl AdjVar {
+            pub const ALL: &'static [AdjVar] = &[$( AdjVar::$var ),*];
+
+            /// The variable with the id `id`, or `None` if it isn't known
+            pub fn from_id(id: u8) -> Option<Self> {
+                Self::from_slice(&[id]).ok()
+            }
+
+            pub fn id(self) -> u8 {
+                self as u8
+            }
+
+            /// The variable's name in the protocol specification, such as `"RC_SPEED_PITCH"`
+            pub fn name(self) -> &'static str {
+                match self {
+                    $( Self::$var => $name, )*
+                }
+            }
+
+            /// The variable named `name` in the protocol specification
+            pub fn from_name(name: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|var| var.name() == name)
+            }
+
+            /// The values the board accepts for the variable
+            pub fn range(self) -> RangeInclusive<i32> {
+                match self {
+                    $( Self::$var => $range, )*
+                }
+            }
+        }
+    };
+}
+
+adj_vars! {
+    PRoll = 0, "P_ROLL", 0..=255;
+    PPitch = 1, "P_PITCH", 0..=255;
+    PYaw = 2, "P_YAW", 0..=255;
+    IRoll = 3, "I_ROLL", 0..=255;
+    IPitch = 4, "I_PITCH", 0..=255;
+    IYaw = 5, "I_YAW", 0..=255;
+    DRoll = 6, "D_ROLL", 0..=255;
+    DPitch = 7, "D_PITCH", 0..=255;
+    DYaw = 8, "D_YAW", 0..=255;
+    PowerRoll = 9, "POWER_ROLL", 0..=255;
+    PowerPitch = 10, "POWER_PITCH", 0..=255;
+    PowerYaw = 11, "POWER_YAW", 0..=255;
+    AccLimiter = 12, "ACC_LIMITER", 0..=200;
+    FollowSpeedRoll = 13, "FOLLOW_SPEED_ROLL", 0..=255;
+    FollowSpeedPitch = 14, "FOLLOW_SPEED_PITCH", 0..=255;
+    FollowSpeedYaw = 15, "FOLLOW_SPEED_YAW", 0..=255;
+    FollowLpfRoll = 16, "FOLLOW_LPF_ROLL", 0..=16;
+    FollowLpfPitch = 17, "FOLLOW_LPF_PITCH", 0..=16;
+    FollowLpfYaw = 18, "FOLLOW_LPF_YAW", 0..=16;
+    RcSpeedRoll = 19, "RC_SPEED_ROLL", 0..=255;
+    RcSpeedPitch = 20, "RC_SPEED_PITCH", 0..=255;
+    RcSpeedYaw = 21, "RC_SPEED_YAW", 0..=255;
+    RcLpfRoll = 22, "RC_LPF_ROLL", 0..=16;
+    RcLpfPitch = 23, "RC_LPF_PITCH", 0..=16;
+    RcLpfYaw = 24, "RC_LPF_YAW", 0..=16;
+    RcTrimRoll = 25, "RC_TRIM_ROLL", -127..=127;
+    RcTrimPitch = 26, "RC_TRIM_PITCH", -127..=127;
+    RcTrimYaw = 27, "RC_TRIM_YAW", -127..=127;
+    RcDeadband = 28, "RC_DEADBAND", 0..=255;
+    RcExpoRate = 29, "RC_EXPO_RATE", 0..=100;
+    FollowMode = 30, "FOLLOW_MODE", 0..=2;
+    RcFollowYaw = 31, "RC_FOLLOW_YAW", 0..=2;
+    FollowDeadband = 32, "FOLLOW_DEADBAND", 0..=255;
+    FollowExpoRate = 33, "FOLLOW_EXPO_RATE", 0..=100;
+    FollowRollMixStart = 34, "FOLLOW_ROLL_MIX_START", 0..=90;
+    FollowRollMixRange = 35, "FOLLOW_ROLL_MIX_RANGE", 0..=90;
+    GyroTrust = 36, "GYRO_TRUST", 0..=255;
+    FrameHeadingAngle = 37, "FRAME_HEADING_ANGLE", -1800..=1800;
+    GyroHeadingCorrection = 38, "GYRO_HEADING_CORRECTION", -20000..=20000;
+    AccLimiterRoll = 39, "ACC_LIMITER_ROLL", 0..=1275;
+    AccLimiterPitch = 40, "ACC_LIMITER_PITCH", 0..=1275;
+    AccLimiterYaw = 41, "ACC_LIMITER_YAW", 0..=1275;
+}
+
+/// The value of one adjustable variable, by id so variables this crate doesn't know can be set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdjVarValue {
+    pub id: u8,
+    pub value: i32,
+}
+
+impl AdjVarValue {
+    /// The variable being set, or `None` if its id isn't known
+    pub fn var(&self) -> Option<AdjVar> {
+        AdjVar::from_id(self.id)
+    }
+}
+
+impl Transmit for AdjVarValue {
+    /// Rejects values outside the range of known variables. Unknown ids are passed on unchecked.
+    fn validate(&self) -> io::Result<()> {
+        match self.var() {
+            Some(var) if !var.range().contains(&self.value) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} out of range: {}", var.name(), self.value),
+            )),
+            _ => Ok(()),
+        }
+    }
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let value = Self {
+            id: reader.read_u8()?,
+            value: reader.read_i32::<LittleEndian>()?,
+        };
+        value.validate()?;
+        Ok(value)
+    }
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        self.validate()?;
+        writer.write_u8(self.id)?;
+        writer.write_i32::<LittleEndian>(self.value)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Command, Transmit)]
+#[id(31)]
+#[direction(both)]
+/// CMD_SET_ADJ_VARS_VAL – sets adjustable variables, and is also the board's answer to
+/// `CMD_GET_ADJ_VARS_VAL`. Build it by chaining `set` calls, e.g.
+/// `SetAdjVarsVal::new().set(AdjVar::RcSpeedPitch, 50)`.
+pub struct SetAdjVarsVal {
+    pub vars: CountPrefixed<AdjVarValue>,
+}
+
+impl SetAdjVarsVal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `var` with `value`. Values out of the variable's range are rejected when sending.
+    pub fn set(self, var: AdjVar, value: i32) -> Self {
+        self.set_raw(var.id(), value)
+    }
+
+    /// Adds the variable with the id `id`, for variables without an `AdjVar`
+    pub fn set_raw(mut self, id: u8, value: i32) -> Self {
+        self.vars.0.push(AdjVarValue { id, value });
+        self
+    }
+}
+
+/// An RC input whose position triggers an action, such as switching profiles, from the
+/// adjustable variables configuration
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct AdjVarsTriggerSlot {
+    pub source: RcSource,
+    /// The menu command run for each of the five ranges the input's position is divided into
+    pub actions: [u8; 5],
+}
+
+/// An RC input that sets an adjustable variable in proportion to its position
+#[derive(Debug, Clone, PartialEq, Transmit)]
+pub struct AdjVarsAnalogSlot {
+    pub source: RcSource,
+    /// Id of the variable adjusted, kept as is so slots holding variables this crate doesn't know
+    /// still decode
+    pub var_id: u8,
+    /// The value at the input's lowest position, scaled to 0-255 over the variable's range
+    pub min: u8,
+    /// The value at the input's highest position, scaled like `min`
+    pub max: u8,
+}
+
+impl AdjVarsAnalogSlot {
+    /// The variable adjusted, or `None` if its id isn't known
+    pub fn var(&self) -> Option<AdjVar> {
+        AdjVar::from_id(self.var_id)
+    }
+
+    pub fn set_var(&mut self, var: AdjVar) {
+        self.var_id = var.id();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(43)]
+#[direction(incoming)]
+/// CMD_READ_ADJ_VARS_CFG – which RC inputs trigger actions and adjust variables, sent in
+/// response to an empty request with the same id
+pub struct ReadAdjVarsCfg {
+    pub trigger_slots: [AdjVarsTriggerSlot; 10],
+    pub analog_slots: [AdjVarsAnalogSlot; 15],
+    _reserved: Reserved<8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(44)]
+#[direction(outgoing)]
+/// CMD_WRITE_ADJ_VARS_CFG – sets the adjustable variables configuration. Usually built from a
+/// `ReadAdjVarsCfg` with the slots to change modified.
+pub struct WriteAdjVarsCfg {
+    pub trigger_slots: [AdjVarsTriggerSlot; 10],
+    pub analog_slots: [AdjVarsAnalogSlot; 15],
+    _reserved: Reserved<8>,
+}
+
+impl From<&ReadAdjVarsCfg> for WriteAdjVarsCfg {
+    fn from(cfg: &ReadAdjVarsCfg) -> Self {
+        Self {
+            trigger_slots: cfg.trigger_slots.clone(),
+            analog_slots: cfg.analog_slots.clone(),
+            _reserved: Reserved,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command)]
+#[id(67)]
+#[direction(incoming)]
+/// CMD_CONFIRM – the board accepted a command
+pub struct Confirm {
+    pub cmd_id: u8,
+    /// Extra data whose meaning depends on the confirmed command, often empty
+    pub data: Vec<u8>,
+}
+
+/// `Confirm::data` decoded according to the confirmed command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfirmData<'a> {
+    None,
+    /// The profile that parameters were written to
+    Profile(ProfileId),
+    /// Data this crate doesn't know the meaning of
+    Raw(&'a [u8]),
+}
+
+/// CMD_WRITE_PARAMS, the older command for writing a profile's parameters. Its confirmation
+/// echoes the profile like `CMD_WRITE_PARAMS_3`'s does.
+const CMD_WRITE_PARAMS: u8 = 87;
+
+impl Confirm {
+    /// Decodes the extra data for the commands it's known for, giving the raw bytes otherwise.
+    pub fn typed_data(&self) -> ConfirmData<'_> {
+        match (self.cmd_id, &self.data[..]) {
+            (_, []) => ConfirmData::None,
+            (WriteParams3::ID, data @ [_]) | (CMD_WRITE_PARAMS, data @ [_]) => {
+                match ProfileId::from_slice(data) {
+                    Ok(profile) => ConfirmData::Profile(profile),
+                    Err(_) => ConfirmData::Raw(data),
+                }
+            }
+            (_, data) => ConfirmData::Raw(data),
+        }
+    }
+
+    /// The CRC16 of the confirmed frame, for firmware that echoes it back as two bytes of data
+    /// so the host can check the board received exactly what was sent. See `verify_confirm`.
+    ///
+    /// The data is only taken to be a CRC when it is exactly two bytes, so this can't tell it
+    /// apart from other two byte data.
+    pub fn echoed_crc(&self) -> Option<u16> {
+        match self.data[..] {
+            [low, high] => Some(u16::from_le_bytes([low, high])),
+            _ => None,
+        }
+    }
+}
+
+impl Transmit for Confirm {
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let cmd_id = reader.read_u8()?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(Self { cmd_id, data })
+    }
+
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u8(self.cmd_id)?;
+        writer.write_all(&self.data)
+    }
+}
+
+/// Error codes reported in `CMD_ERROR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardError {
+    /// The command's payload had the wrong size
+    CmdSize,
+    /// The command's parameters were invalid
+    WrongParams,
+    /// The device id couldn't be read
+    GetDeviceId,
+    /// Signing or verifying failed
+    Crypto,
+    /// Battery calibration failed
+    CalibrateBat,
+    /// The command isn't supported by the firmware
+    UnknownCommand,
+    /// An error code this crate doesn't know about
+    Unknown(u8),
+}
+
+impl From<u8> for BoardError {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => Self::CmdSize,
+            2 => Self::WrongParams,
+            3 => Self::GetDeviceId,
+            4 => Self::Crypto,
+            5 => Self::CalibrateBat,
+            6 => Self::UnknownCommand,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl From<BoardError> for u8 {
+    fn from(err: BoardError) -> Self {
+        match err {
+            BoardError::CmdSize => 1,
+            BoardError::WrongParams => 2,
+            BoardError::GetDeviceId => 3,
+            BoardError::Crypto => 4,
+            BoardError::CalibrateBat => 5,
+            BoardError::UnknownCommand => 6,
+            BoardError::Unknown(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CmdSize => write!(f, "wrong command size"),
+            Self::WrongParams => write!(f, "wrong command parameters"),
+            Self::GetDeviceId => write!(f, "failed to get device id"),
+            Self::Crypto => write!(f, "cryptographic error"),
+            Self::CalibrateBat => write!(f, "battery calibration failed"),
+            Self::UnknownCommand => write!(f, "unknown command"),
+            Self::Unknown(code) => write!(f, "unknown error code {}", code),
+        }
+    }
+}
+
+impl Transmit for BoardError {
+    #[inline]
+    fn from_reader<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        reader.read_u8().map(Self::from)
+    }
+    #[inline]
+    fn to_writer<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u8((*self).into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Command, Transmit)]
+#[id(255)]
+#[direction(incoming)]
+/// CMD_ERROR – the board couldn't execute a command
+pub struct CmdError {
+    pub error_code: BoardError,
+    /// Meaning depends on the error code
+    pub error_data: [u8; 4],
+}
+
+/// Commands whose payload starts with a sub-id selecting what the rest of the payload means
+const SUB_ID_COMMANDS: &[u8] = &[
+    110, // CMD_EXT_IMU_CMD
+];
+
+/// Whether the payload of the command `id` starts with a sub-id.
+pub fn has_sub_id(id: u8) -> bool {
+    SUB_ID_COMMANDS.contains(&id)
+}
+
+/// A command to be sent to the board, with its payload already serialized
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingCommand {
+    pub id: u8,
+    /// Written as the first payload byte, for commands addressed by an id and a sub-id
+    pub sub_id: Option<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl OutgoingCommand {
+    /// Serializes a command that can be sent to the board, taking the id from its type. The
+    /// length in the frame header is always computed from the payload when framing, so the
+    /// result is consistent by construction. Fails if the payload is too long for a frame.
+    pub fn from_command<C: Outgoing>(cmd: &C) -> io::Result<Self> {
+        let mut payload = Vec::new();
+        cmd.write_payload(&mut payload)?;
+        Self::with_payload(C::ID, payload)
+    }
+
+    fn with_payload(id: u8, payload: Vec<u8>) -> io::Result<Self> {
+        if payload.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} byte payload of command {} doesn't fit in a frame",
+                    payload.len(),
+                    id
+                ),
+            ));
+        }
+        Ok(Self {
+            id,
+            sub_id: None,
+            payload,
+        })
+    }
+
+    /// The id of the command the board answers this one with. Requests for data are answered with
+    /// the data, usually under the same id, and other commands with a `CMD_CONFIRM`. Returns
+    /// `None` for commands that aren't answered and for unknown commands.
+    pub fn response_id(&self) -> Option<u8> {
+        if UNANSWERED.contains(&self.id) {
+            return None;
+        }
+        match RESPONSE_IDS.iter().find(|(request, _)| *request == self.id) {
+            Some((_, response)) => Some(*response),
+            None => command_name(self.id).map(|_| Confirm::ID),
+        }
+    }
+}
+
+/// Generates `IncomingCommand` with a variant for each listed `Incoming` command, named after its
+/// type, along with the decoding dispatch and `id`. New commands only need adding to the list.
+macro_rules! incoming_commands {
+    ($( $cmd:ident $(or $fallback:ident)?, )*) => {
+        /// A command received from the board, decoded according to its id.
+        ///
+        /// Ids are only unique within a direction: `CMD_CONTROL` and `CMD_CONFIRM` share one, as
+        /// do many requests and their responses. Received frames are always decoded as the
+        /// `Incoming` command for their id.
+        ///
+        /// Older firmware sends some commands with fewer fields. When a payload is too short for
+        /// such a command, it is decoded as the smaller command it extends instead, so the
+        /// variant's id can differ from the frame's:
+        ///
+        /// - `CMD_REALTIME_DATA_4` falls back to `CMD_REALTIME_DATA_3`
+        ///
+        /// If the payload is too short for that as well, the original error is returned.
+        #[derive(Debug, PartialEq)]
+        pub enum IncomingCommand {
+            $( $cmd($cmd), )*
+            /// A command this crate doesn't know how to decode. The sub-id is split off the
+            /// payload for commands that carry one.
+            Unknown {
+                id: u8,
+                sub_id: Option<u8>,
+                payload: Vec<u8>,
+            },
+        }
+
+        impl IncomingCommand {
+            /// Decodes `payload` as the command identified by `id`. Bytes following the
+            /// command's fields, such as fields added by newer firmware, are ignored.
+            pub(crate) fn from_payload(id: u8, payload: &[u8]) -> io::Result<Self> {
+                Self::from_payload_used(id, payload).map(|(cmd, _)| cmd)
+            }
+
+            /// Like `from_payload`, but also returns how many bytes of the payload were decoded.
+            pub(crate) fn from_payload_used(id: u8, payload: &[u8]) -> io::Result<(Self, usize)> {
+                // Only frames the board sends are decoded here, so the listed types must be
+                // `Incoming` and there can only be one for each id. Commands sharing an id with
+                // their request, such as `GetAngles` and `GetAnglesResponse`, are decoded as the
+                // response.
+                fn incoming<C: Incoming, R: ReadBytesExt>(reader: &mut R) -> io::Result<C> {
+                    C::parse_payload(reader)
+                }
+
+                let mut reader = io::Cursor::new(payload);
+                // Two types with the same id would make the second arm unreachable
+                #[deny(unreachable_patterns)]
+                let cmd = match id {
+                    $( $cmd::ID => match incoming::<$cmd, _>(&mut reader) {
+                        Ok(cmd) => Self::$cmd(cmd),
+                        $(
+                            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                                reader.set_position(0);
+                                match incoming::<$fallback, _>(&mut reader) {
+                                    Ok(cmd) => Self::$fallback(cmd),
+                                    Err(_) => return Err(err),
+                                }
+                            }
+                        )?
+                        Err(err) => return Err(err),
+                    }, )*
+                    _ => match payload.split_first() {
+                        Some((sub_id, payload)) if has_sub_id(id) => Self::Unknown {
+                            id,
+                            sub_id: Some(*sub_id),
+                            payload: payload.to_vec(),
+                        },
+                        _ => Self::Unknown {
+                            id,
+                            sub_id: None,
+                            payload: payload.to_vec(),
+                        },
+                    },
+                };
+                let used = match cmd {
+                    Self::Unknown { .. } => payload.len(),
+                    _ => reader.position() as usize,
+                };
+                Ok((cmd, used))
+            }
+
+            pub fn id(&self) -> u8 {
+                match self {
+                    $( Self::$cmd(_) => $cmd::ID, )*
+                    Self::Unknown { id, .. } => *id,
+                }
+            }
+
+            /// The decoded command, or `None` for `Unknown`
+            pub(crate) fn typed(&self) -> Option<&dyn fmt::Debug> {
+                match self {
+                    $( Self::$cmd(cmd) => Some(cmd), )*
+                    Self::Unknown { .. } => None,
+                }
+            }
+        }
+    };
+}
+
+incoming_commands! {
+    BoardInfo,
+    BoardInfo3,
+    ReadParams3,
+    GetAnglesResponse,
+    CmdError,
+    Confirm,
+    CanDeviceScanResponse,
+    RealtimeDataCanDrv,
+    CoggingCalibInfo,
+    RealtimeData3,
+    RealtimeData4 or RealtimeData3,
+    SetAdjVarsVal,
+    ReadAdjVarsCfg,
+    ReadParamsExt,
+    ReadParamsExt2,
+    ReadParamsExt3,
+}
+
+impl IncomingCommand {
+    /// The specification's name for this command, or `None` for an unrecognised id.
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Confirm(_) => Some("CMD_CONFIRM"),
+            _ => command_name(self.id()),
+        }
+    }
+}
+
+/// The firmware version each command was introduced in, encoded like `BoardInfo::firmware_ver`.
+/// Commands that aren't listed are part of the original protocol.
+const INTRODUCED_IN: &[(u8, u16)] = &[
+    (BoardInfo3::ID, 2400),
+    (ReadParams3::ID, 2400),
+    (WriteParams3::ID, 2400),
+    (RealtimeData3::ID, 2400),
+    (SelectImu3::ID, 2400),
+    (RealtimeData4::ID, 2400),
+    (28, 2400), // CMD_READ_PROFILE_NAMES
+    (29, 2400), // CMD_WRITE_PROFILE_NAMES
+    (SetAdjVarsVal::ID, 2410),
+    (ReadParamsExt::ID, 2420),
+    (34, 2420), // CMD_WRITE_PARAMS_EXT
+    (ReadAdjVarsCfg::ID, 2430),
+    (WriteAdjVarsCfg::ID, 2430),
+    (ReadParamsExt2::ID, 2550),
+    (63, 2550), // CMD_WRITE_PARAMS_EXT2
+    (64, 2550), // CMD_GET_ADJ_VARS_VAL
+    (DataStreamInterval::ID, 2600),
+    (RealtimeDataCustom::ID, 2600),
+    (89, 2600), // CMD_BEEP_SOUND
+    (90, 2610), // CMD_CONTROL_CONFIG
+    (CoggingCalibInfo::ID, 2620),
+    (CalibCogging::ID, 2620),
+    (95, 2620), // CMD_PROFILE_SET
+    (CanDeviceScan::ID, 2630),
+    (100, 2640), // CMD_READ_RC_INPUTS
+    (RealtimeDataCanDrv::ID, 2650),
+    (ReadParamsExt3::ID, 2660),
+    (105, 2660), // CMD_WRITE_PARAMS_EXT3
+];
+
+/// Whether a board running `firmware_ver` understands the command `cmd_id`, so requests can be
+/// checked before sending them instead of getting a `CMD_ERROR` back.
+pub fn supports(firmware_ver: u16, cmd_id: u8) -> bool {
+    INTRODUCED_IN
+        .iter()
+        .find(|(id, _)| *id == cmd_id)
+        .is_none_or(|(_, introduced)| firmware_ver >= *introduced)
+}
+
+/// Whether a command is sent to the board, received from it, or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Sent by the board
+    Incoming,
+    /// Sent to the board
+    Outgoing,
+    /// Sent in both directions, usually a request answered under the same id
+    Both,
+}
+
+impl Direction {
+    /// Whether a command with this direction can be sent to the board
+    pub fn is_outgoing(self) -> bool {
+        self != Direction::Incoming
+    }
+
+    /// Whether a command with this direction can be received from the board
+    pub fn is_incoming(self) -> bool {
+        self != Direction::Outgoing
+    }
+}
+
+/// An entry in `COMMANDS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub id: u8,
+    /// The name in the protocol specification, such as `"CMD_CONTROL"`
+    pub name: &'static str,
+    pub direction: Direction,
+}
+
+impl CommandInfo {
+    const fn new(id: u8, name: &'static str, direction: Direction) -> Self {
+        Self {
+            id,
+            name,
+            direction,
+        }
+    }
+}
+
+/// Every command in the protocol specification, ordered by id. Where an id means different
+/// things in each direction, such as `CMD_CONTROL` and `CMD_CONFIRM`, it has an entry for each
+/// with the outgoing one first.
+pub const COMMANDS: &[CommandInfo] = {
+    use Direction::*;
+    &[
+        CommandInfo::new(20, "CMD_BOARD_INFO_3", Both),
+        CommandInfo::new(21, "CMD_READ_PARAMS_3", Both),
+        CommandInfo::new(22, "CMD_WRITE_PARAMS_3", Outgoing),
+        CommandInfo::new(23, "CMD_REALTIME_DATA_3", Both),
+        CommandInfo::new(24, "CMD_SELECT_IMU_3", Outgoing),
+        CommandInfo::new(25, "CMD_REALTIME_DATA_4", Both),
+        CommandInfo::new(26, "CMD_ENCODERS_CALIB_OFFSET_4", Outgoing),
+        CommandInfo::new(27, "CMD_ENCODERS_CALIB_FLD_OFFSET_4", Outgoing),
+        CommandInfo::new(28, "CMD_READ_PROFILE_NAMES", Both),
+        CommandInfo::new(29, "CMD_WRITE_PROFILE_NAMES", Outgoing),
+        CommandInfo::new(30, "CMD_QUEUE_PARAMS_INFO_3", Both),
+        CommandInfo::new(31, "CMD_SET_ADJ_VARS_VAL", Both),
+        CommandInfo::new(32, "CMD_SAVE_PARAMS_3", Outgoing),
+        CommandInfo::new(33, "CMD_READ_PARAMS_EXT", Both),
+        CommandInfo::new(34, "CMD_WRITE_PARAMS_EXT", Outgoing),
+        CommandInfo::new(35, "CMD_AUTO_PID", Outgoing),
+        CommandInfo::new(36, "CMD_SERVO_OUT", Outgoing),
+        CommandInfo::new(37, "CMD_BODE_TEST_START_STOP", Outgoing),
+        CommandInfo::new(38, "CMD_BODE_TEST_DATA", Incoming),
+        CommandInfo::new(39, "CMD_I2C_WRITE_REG_BUF", Outgoing),
+        CommandInfo::new(40, "CMD_I2C_READ_REG_BUF", Both),
+        CommandInfo::new(41, "CMD_WRITE_EXTERNAL_DATA", Outgoing),
+        CommandInfo::new(42, "CMD_READ_EXTERNAL_DATA", Both),
+        CommandInfo::new(43, "CMD_READ_ADJ_VARS_CFG", Both),
+        CommandInfo::new(44, "CMD_WRITE_ADJ_VARS_CFG", Outgoing),
+        CommandInfo::new(45, "CMD_API_VIRT_CH_CONTROL", Outgoing),
+        CommandInfo::new(46, "CMD_ADJ_VARS_STATE", Both),
+        CommandInfo::new(47, "CMD_EEPROM_WRITE", Outgoing),
+        CommandInfo::new(48, "CMD_EEPROM_READ", Both),
+        CommandInfo::new(49, "CMD_CALIB_INFO", Both),
+        CommandInfo::new(51, "CMD_BOOT_MODE_3", Outgoing),
+        CommandInfo::new(52, "CMD_SYSTEM_STATE", Both),
+        CommandInfo::new(53, "CMD_READ_FILE", Both),
+        CommandInfo::new(54, "CMD_WRITE_FILE", Outgoing),
+        CommandInfo::new(55, "CMD_FS_CLEAR_ALL", Outgoing),
+        CommandInfo::new(56, "CMD_AHRS_HELPER", Outgoing),
+        CommandInfo::new(57, "CMD_RUN_SCRIPT", Outgoing),
+        CommandInfo::new(58, "CMD_SCRIPT_DEBUG", Incoming),
+        CommandInfo::new(59, "CMD_CALIB_MAG", Outgoing),
+        CommandInfo::new(61, "CMD_GET_ANGLES_EXT", Both),
+        CommandInfo::new(62, "CMD_READ_PARAMS_EXT2", Both),
+        CommandInfo::new(63, "CMD_WRITE_PARAMS_EXT2", Outgoing),
+        CommandInfo::new(64, "CMD_GET_ADJ_VARS_VAL", Outgoing),
+        CommandInfo::new(65, "CMD_CALIB_ACC", Outgoing),
+        CommandInfo::new(66, "CMD_CALIB_BAT", Outgoing),
+        CommandInfo::new(67, "CMD_CONTROL", Outgoing),
+        CommandInfo::new(67, "CMD_CONFIRM", Incoming),
+        CommandInfo::new(68, "CMD_REALTIME_DATA", Both),
+        CommandInfo::new(69, "CMD_EXECUTE_MENU", Outgoing),
+        CommandInfo::new(70, "CMD_USE_DEFAULTS", Outgoing),
+        CommandInfo::new(71, "CMD_CALIB_EXT_GAIN", Outgoing),
+        CommandInfo::new(72, "CMD_HELPER_DATA", Outgoing),
+        CommandInfo::new(73, "CMD_GET_ANGLES", Both),
+        CommandInfo::new(74, "CMD_CALIB_MOTOR_MAG_LINK", Outgoing),
+        CommandInfo::new(75, "CMD_GYRO_CORRECTION", Outgoing),
+        CommandInfo::new(77, "CMD_MOTORS_ON", Outgoing),
+        CommandInfo::new(79, "CMD_CALIB_OFFSET", Outgoing),
+        CommandInfo::new(80, "CMD_CALIB_POLES", Outgoing),
+        CommandInfo::new(82, "CMD_READ_PARAMS", Both),
+        CommandInfo::new(84, "CMD_TRIGGER_PIN", Outgoing),
+        CommandInfo::new(85, "CMD_DATA_STREAM_INTERVAL", Outgoing),
+        CommandInfo::new(86, "CMD_BOARD_INFO", Both),
+        CommandInfo::new(87, "CMD_WRITE_PARAMS", Outgoing),
+        CommandInfo::new(88, "CMD_REALTIME_DATA_CUSTOM", Both),
+        CommandInfo::new(89, "CMD_BEEP_SOUND", Outgoing),
+        CommandInfo::new(90, "CMD_CONTROL_CONFIG", Outgoing),
+        CommandInfo::new(92, "CMD_COGGING_CALIB_INFO", Both),
+        CommandInfo::new(93, "CMD_CALIB_COGGING", Outgoing),
+        CommandInfo::new(95, "CMD_PROFILE_SET", Outgoing),
+        CommandInfo::new(96, "CMD_CAN_DEVICE_SCAN", Both),
+        CommandInfo::new(100, "CMD_READ_RC_INPUTS", Both),
+        CommandInfo::new(101, "CMD_REALTIME_DATA_CAN_DRV", Both),
+        CommandInfo::new(103, "CMD_CALIB_GYRO", Outgoing),
+        CommandInfo::new(104, "CMD_READ_PARAMS_EXT3", Both),
+        CommandInfo::new(105, "CMD_WRITE_PARAMS_EXT3", Outgoing),
+        CommandInfo::new(109, "CMD_MOTORS_OFF", Outgoing),
+        CommandInfo::new(110, "CMD_EXT_IMU_CMD", Outgoing),
+        CommandInfo::new(113, "CMD_SEND_CORR_CURVE", Outgoing),
+        CommandInfo::new(114, "CMD_RESET", Outgoing),
+        CommandInfo::new(249, "CMD_SET_DEBUG_PORT", Outgoing),
+        CommandInfo::new(250, "CMD_MAVLINK_INFO", Incoming),
+        CommandInfo::new(251, "CMD_MAVLINK_DEBUG", Incoming),
+        CommandInfo::new(253, "CMD_DEBUG_VARS_INFO_3", Both),
+        CommandInfo::new(254, "CMD_DEBUG_VARS_3", Both),
+        CommandInfo::new(255, "CMD_ERROR", Incoming),
+    ]
+};
+
+/// Requests that are answered with data instead of a confirmation, and the id of the answer
+const RESPONSE_IDS: &[(u8, u8)] = &[
+    (BoardInfo3::ID, BoardInfo3::ID),
+    (ReadParams3::ID, ReadParams3::ID),
+    (RealtimeData3::ID, RealtimeData3::ID),
+    (RealtimeData4::ID, RealtimeData4::ID),
+    (28, 28), // CMD_READ_PROFILE_NAMES
+    (ReadParamsExt::ID, ReadParamsExt::ID),
+    (ReadAdjVarsCfg::ID, ReadAdjVarsCfg::ID),
+    (49, 49), // CMD_CALIB_INFO
+    (61, 61), // CMD_GET_ANGLES_EXT
+    (ReadParamsExt2::ID, ReadParamsExt2::ID),
+    (64, SetAdjVarsVal::ID), // CMD_GET_ADJ_VARS_VAL
+    (68, 68),                // CMD_REALTIME_DATA
+    (GetAngles::ID, GetAnglesResponse::ID),
+    (82, 82), // CMD_READ_PARAMS
+    (BoardInfo::ID, BoardInfo::ID),
+    (RealtimeDataCustom::ID, RealtimeDataCustom::ID),
+    (CoggingCalibInfo::ID, CoggingCalibInfo::ID),
+    (CanDeviceScan::ID, CanDeviceScanResponse::ID),
+    (100, 100), // CMD_READ_RC_INPUTS
+    (ReadParamsExt3::ID, ReadParamsExt3::ID),
+    (253, 253), // CMD_DEBUG_VARS_INFO_3
+    (254, 254), // CMD_DEBUG_VARS_3
+];
+
+/// Commands the board doesn't answer at all, since they are sent continuously
+const UNANSWERED: &[u8] = &[
+    45, // CMD_API_VIRT_CH_CONTROL
+    Control::ID,
+];
+
+/// The specification's name for the command `id`, such as `"CMD_CONTROL"`, for logging. Where
+/// an id means different things in each direction this is the name of the outgoing command.
+pub fn command_name(id: u8) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|info| info.id == id)
+        .map(|info| info.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_command_exact;
+
+    #[test]
+    fn reserved_contents_are_ignored() {
+        let mut payload = vec![3, 0x01, 0x09, 0, 0, 0, 0];
+        payload.extend_from_slice(&[0; 8]);
+        let mut junk = payload.clone();
+        payload.extend_from_slice(&[0; 7]);
+        junk.extend_from_slice(&[0xAA; 7]);
+
+        let info = BoardInfo::from_reader(&mut &payload[..]).unwrap();
+        assert_eq!(info, BoardInfo::from_reader(&mut &junk[..]).unwrap());
+
+        let mut written = Vec::new();
+        BoardInfo::from_reader(&mut &junk[..])
+            .unwrap()
+            .to_writer(&mut written)
+            .unwrap();
+        assert_eq!(written, payload);
+    }
+
+    #[test]
+    fn supports_checks_introduced_version() {
+        assert!(supports(2305, BoardInfo::ID));
+        assert!(!supports(2305, ReadParams3::ID));
+        assert!(supports(2400, ReadParams3::ID));
+        assert!(supports(2687, ReadParams3::ID));
+    }
+
+    fn round_trip<T: Transmit>(value: T, bytes: &[u8]) -> T {
+        let written = value.to_vec().unwrap();
+        assert_eq!(written, bytes);
+        T::from_slice(&written).unwrap()
+    }
+
+    #[test]
+    fn arrays_write_each_element_little_endian() {
+        assert_eq!(
+            round_trip([0x1234u16, 0x5678], &[0x34, 0x12, 0x78, 0x56]),
+            [0x1234, 0x5678]
+        );
+        assert_eq!(
+            round_trip(
+                [-2i16, 0x0102, i16::MIN],
+                &[0xFE, 0xFF, 0x02, 0x01, 0x00, 0x80]
+            ),
+            [-2, 0x0102, i16::MIN]
+        );
+    }
+
+    #[test]
+    fn arrays_of_tuples_write_each_tuple_in_turn() {
+        let records = [(-2i16, 1u8), (0x0102, 2), (i16::MIN, 0xFF)];
+        let bytes = [0xFE, 0xFF, 0x01, 0x02, 0x01, 0x02, 0x00, 0x80, 0xFF];
+        assert_eq!(round_trip(records, &bytes), records);
+
+        let mut read = [(0, 0); 3];
+        read.read_into(&mut &bytes[..]).unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn tuples_with_restricted_values_are_built_from_valid_reads() {
+        let value = (true, PWMFrequency::High, 0xBEEFu16);
+        assert_eq!(round_trip(value.clone(), &[1, 1, 0xEF, 0xBE]), value);
+        // Invalid bytes for the bool and the enum are errors rather than values
+        assert!(<(bool, PWMFrequency, u16)>::from_slice(&[2, 1, 0, 0]).is_err());
+        assert!(<(bool, PWMFrequency, u16)>::from_slice(&[1, 7, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn tuples_of_flags_and_enums_use_each_members_impl() {
+        #[derive(Debug, Clone, PartialEq, Transmit)]
+        struct Capabilities {
+            features: (BoardInfoFeatures, PWMFrequency),
+        }
+
+        let caps = Capabilities {
+            features: (
+                BoardInfoFeatures::ENCODERS | BoardInfoFeatures::CURRENT_SENSOR,
+                PWMFrequency::Pitch,
+            ),
+        };
+        assert_eq!(round_trip(caps.clone(), &[0x24, 0x00, 0x02]), caps);
+        // Each member still rejects what its own impl rejects
+        assert!(Capabilities::from_slice(&[0x00, 0x80, 0x00]).is_err());
+        assert!(Capabilities::from_slice(&[0x01, 0x00, 0x03]).is_err());
+    }
+
+    #[test]
+    fn gimbal_speed_conversions() {
+        assert_eq!(round_trip(GimbalSpeed(-2), &[0xFE, 0xFF]), GimbalSpeed(-2));
+        assert!((GimbalSpeed(8192).to_deg_per_sec() - 1000.0305).abs() < 1e-3);
+        assert_eq!(
+            GimbalSpeed::from_deg_per_sec(1000.0),
+            Some(GimbalSpeed(8192))
+        );
+
+        let unit = GimbalSpeed::UNIT;
+        assert_eq!(
+            GimbalSpeed::from_deg_per_sec(0.4 * unit),
+            Some(GimbalSpeed(0))
+        );
+        assert_eq!(
+            GimbalSpeed::from_deg_per_sec(0.6 * unit),
+            Some(GimbalSpeed(1))
+        );
+        assert_eq!(
+            GimbalSpeed::from_deg_per_sec(-0.6 * unit),
+            Some(GimbalSpeed(-1))
+        );
+
+        for limit in [i16::MIN, i16::MAX] {
+            let deg = GimbalSpeed(limit).to_deg_per_sec();
+            assert_eq!(GimbalSpeed::from_deg_per_sec(deg), Some(GimbalSpeed(limit)));
+            assert_eq!(GimbalSpeed::from_deg_per_sec(deg * 1.001), None);
+        }
+        assert_eq!(GimbalSpeed::from_deg_per_sec(f64::NAN), None);
+    }
+
+    #[test]
+    fn try_new_checks_ranges() {
+        let motor = MotorStatus::try_new(10, 20, 30, 200, false, 22).unwrap();
+        assert_eq!(motor.pid(), (10, 20, 30));
+
+        let mode = RcMode {
+            mode: RcModeControl::Angle,
+            inverted: false,
+        };
+        let rc = |lpf| RcStatus::try_new(-90, 90, mode.clone(), lpf, 30, RcFollow::Disabled);
+        assert!(rc(15).is_ok());
+        assert_eq!(rc(16).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let cogging = CalibCogging::try_new([true; 3], 0);
+        assert_eq!(cogging.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            CalibCogging::try_new([true; 3], 5).unwrap()._reserved,
+            Reserved
+        );
+    }
+
+    #[test]
+    fn rc_status_rejects_min_angle_above_max_angle() {
+        let mode = RcMode {
+            mode: RcModeControl::Angle,
+            inverted: false,
+        };
+        let err =
+            RcStatus::try_new(100, -100, mode.clone(), 0, 30, RcFollow::Disabled).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "RcStatus.min_angle (100) is greater than max_angle (-100)"
+        );
+        assert!(RcStatus::try_new(100, 100, mode, 0, 30, RcFollow::Disabled).is_ok());
+
+        // Angles are little-endian, so this is min_angle 100 and max_angle -100
+        assert!(RcStatus::from_slice(&[100, 0, 0x9C, 0xFF, 1, 0, 30, 0]).is_err());
+        assert!(RcStatus::from_slice(&[0x9C, 0xFF, 100, 0, 1, 0, 30, 0]).is_ok());
+    }
+
+    #[test]
+    fn adj_vars_by_name() {
+        assert_eq!(
+            AdjVar::from_name("RC_SPEED_PITCH"),
+            Some(AdjVar::RcSpeedPitch)
+        );
+        assert_eq!(AdjVar::RcSpeedPitch.id(), 20);
+        assert_eq!(AdjVar::from_id(41), Some(AdjVar::AccLimiterYaw));
+        assert_eq!(AdjVar::from_id(200), None);
+        assert!(AdjVar::ALL
+            .iter()
+            .all(|var| AdjVar::from_name(var.name()) == Some(*var)));
+
+        let cmd = SetAdjVarsVal::new()
+            .set(AdjVar::RcSpeedPitch, 50)
+            .set(AdjVar::RcTrimYaw, -3)
+            .set_raw(200, 70000);
+        let mut payload = vec![3];
+        payload.extend([20, 50, 0, 0, 0]);
+        payload.extend([27, 0xFD, 0xFF, 0xFF, 0xFF]);
+        payload.extend([200, 0x70, 0x11, 0x01, 0x00]);
+        assert_eq!(round_trip(cmd.clone(), &payload), cmd);
+        assert_eq!(cmd.vars.0[0].var(), Some(AdjVar::RcSpeedPitch));
+
+        let too_fast = SetAdjVarsVal::new().set(AdjVar::RcSpeedPitch, 256);
+        assert_eq!(
+            too_fast.to_vec().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn ids_format_as_hex() {
+        let id = DeviceId([0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0x00]);
+        assert_eq!(id.to_string(), "01:23:45:67:89:AB:CD:EF:00");
+        assert_eq!(format!("{:?}", id), "DeviceId(01:23:45:67:89:AB:CD:EF:00)");
+        assert_eq!(round_trip(id, &id.0), id);
+
+        let mcu = McuId([0xFF; 12]);
+        assert_eq!(mcu.to_string(), ["FF"; 12].join(":"));
+    }
+
+    #[test]
+    fn params_ext_keep_unmodelled_fields() {
+        let mut payload = vec![0; 120];
+        payload[1] = 50; // roll notch_freq[0]
+        payload[119] = 0xAB;
+        let ext = ReadParamsExt::from_slice(&payload).unwrap();
+        assert_eq!(ext.notch_freq[0], [50, 0, 0]);
+        assert_eq!(ext.rest.0.last(), Some(&0xAB));
+        assert_eq!(round_trip(ext.clone(), &payload), ext);
+
+        let mut payload = vec![0; 60];
+        payload[0] = 255;
+        payload[1] = 2; // mav_src
+        let ext2 = ReadParamsExt2::from_slice(&payload).unwrap();
+        assert_eq!(ext2.profile_id, ProfileId::Current);
+        assert_eq!(ext2.mav_src, 2);
+        assert_eq!(round_trip(ext2.clone(), &payload), ext2);
+
+        let ext3 = IncomingCommand::from_payload(ReadParamsExt3::ID, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            ext3,
+            IncomingCommand::ReadParamsExt3(ReadParamsExt3 {
+                profile_id: ProfileId::Index(1),
+                rest: Remaining(vec![2, 3]),
+            })
+        );
+    }
+
+    #[test]
+    fn scaled_fields() {
+        let mut data = RealtimeData3::from_slice(&realtime_data3_payload()).unwrap();
+        data.set_bat_level_scaled(12.345).unwrap();
+        assert_eq!(data.bat_level, 1235);
+        assert!((data.bat_level_scaled() - 12.35).abs() < 1e-9);
+        data.set_bat_level_scaled(655.35).unwrap();
+        assert_eq!(data.bat_level, u16::MAX);
+        assert!(data.set_bat_level_scaled(655.36).is_err());
+        assert!(data.set_bat_level_scaled(-0.01).is_err());
+        assert_eq!(data.bat_level, u16::MAX);
+    }
+
+    #[test]
+    fn battery_and_power() {
+        let mut payload = realtime_data3_payload();
+        payload.resize(124, 0);
+        payload[76..78].copy_from_slice(&2500u16.to_le_bytes()); // current
+        let data = RealtimeData4::from_slice(&payload).unwrap();
+        // bat_level of 1180 is 11.8V
+        assert_eq!(data.base.battery_volts(), 11.8);
+        assert_eq!(data.battery_volts(), 11.8);
+        assert_eq!(data.current_amps(), 2.5);
+        assert!((data.power_watts() - 29.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn short_realtime_data_4_falls_back_to_3() {
+        let payload = realtime_data3_payload();
+        let cmd = IncomingCommand::from_payload(RealtimeData4::ID, &payload).unwrap();
+        match cmd {
+            IncomingCommand::RealtimeData3(data) => assert_eq!(data.bat_level, 1180),
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let err = IncomingCommand::from_payload(RealtimeData4::ID, &payload[..10]).unwrap_err();
+        assert!(err.to_string().starts_with("RealtimeData4."), "{}", err);
+    }
+
+    #[test]
+    fn derive_on_generic_structs() {
+        #[derive(Debug, PartialEq, Transmit)]
+        struct Wrap<T: Transmit>(T, u8);
+
+        #[derive(Debug, PartialEq, Command, Transmit)]
+        #[id(200)]
+        struct Framed<Ext>
+        where
+            Ext: Clone,
+        {
+            profile_id: ProfileId,
+            ext: Ext,
+        }
+
+        assert_eq!(
+            round_trip(Wrap(0x0102u16, 3), &[0x02, 0x01, 3]),
+            Wrap(0x0102, 3)
+        );
+        assert_eq!(
+            round_trip(Wrap(Wrap(true, 1), 2), &[1, 1, 2]),
+            Wrap(Wrap(true, 1), 2)
+        );
+        let framed = Framed {
+            profile_id: ProfileId::Current,
+            ext: [1u8, 2],
+        };
+        assert_eq!(
+            OutgoingCommand::from_command(&framed).unwrap().payload,
+            [255, 1, 2]
+        );
+    }
+
+    #[test]
+    fn read_params_diff_lists_changed_fields() {
+        let old = ReadParams3::from_slice(&read_params3_payload()).unwrap();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.gyro_thrust = old.gyro_thrust.wrapping_add(1);
+        new.use_model = !old.use_model;
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            [
+                (
+                    "gyro_thrust",
+                    old.gyro_thrust.to_string(),
+                    new.gyro_thrust.to_string()
+                ),
+                (
+                    "use_model",
+                    old.use_model.to_string(),
+                    new.use_model.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_into_overwrites_in_place() {
+        let payload = realtime_data3_payload();
+        let expected = RealtimeData3::from_slice(&payload).unwrap();
+        let mut data = RealtimeData3::from_slice(&[0; 63]).unwrap();
+        data.read_into(&mut &payload[..]).unwrap();
+        assert_eq!(data, expected);
+
+        // A failed read keeps the fields before the failure
+        let mut short = data.clone();
+        let err = short.read_into(&mut &[0xFF; 20][..]).unwrap_err();
+        assert!(err.to_string().starts_with("RealtimeData3."));
+        assert_ne!(short, data);
+
+        let mut list = CountPrefixed(Vec::<u8>::with_capacity(8));
+        let capacity = list.0.capacity();
+        list.read_into(&mut &[2, 7, 8][..]).unwrap();
+        assert_eq!(list.0, [7, 8]);
+        assert_eq!(list.0.capacity(), capacity);
+    }
+
+    #[test]
+    fn send_corr_curve_limits_points() {
+        let curve = SendCorrCurve {
+            axis: Axis::Yaw,
+            curve_type: CorrCurveType::Cogging,
+            points: CountPrefixed(vec![-2, 300]),
+        };
+        round_trip(curve.clone(), &[2, 0, 2, 0xFE, 0xFF, 0x2C, 0x01]);
+        assert!(OutgoingCommand::from_command(&curve).is_ok());
+
+        let too_long = SendCorrCurve {
+            points: CountPrefixed(vec![0; SendCorrCurve::MAX_POINTS + 1]),
+            ..curve
+        };
+        assert!(too_long.validate().is_err());
+        assert!(too_long.to_vec().is_err());
+        assert!(SendCorrCurve::from_slice(&[3, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn select_imu_3_round_trips() {
+        let select = SelectImu3 {
+            imu: Imu::Frame,
+            action: SelectImuAction::Select,
+        };
+        round_trip(select.clone(), &[2, 0]);
+        assert_eq!(OutgoingCommand::from_command(&select).unwrap().id, 24);
+        assert_eq!(
+            OutgoingCommand::from_command(&select)
+                .unwrap()
+                .response_id(),
+            Some(Confirm::ID)
+        );
+        assert!(SelectImu3::from_slice(&[0, 0]).is_err());
+        assert!(SelectImu3::from_slice(&[1, 2]).is_err());
+    }
+
+    #[test]
+    fn outgoing_commands_can_be_queued_as_objects() {
+        let control = Control::axis_only(
+            Axis::Yaw,
+            ControlMode::Speed,
+            ControlAxis {
+                speed: GimbalSpeed(100),
+                angle: 0,
+            },
+        );
+        let queue: Vec<Box<dyn OutgoingCommandObj>> =
+            vec![Box::new(control.clone()), Box::new(GetAngles)];
+        assert_eq!(
+            queue.iter().map(|cmd| cmd.name()).collect::<Vec<_>>(),
+            [Some("CMD_CONTROL"), Some("CMD_GET_ANGLES")]
+        );
+        assert_eq!(
+            queue[0].to_outgoing().unwrap(),
+            OutgoingCommand::from_command(&control).unwrap()
+        );
+        assert_eq!(queue[1].to_outgoing().unwrap().payload, []);
+    }
+
+    #[test]
+    fn from_command_rejects_payloads_too_long_for_a_frame() {
+        let mut vars = SetAdjVarsVal::new();
+        for _ in 0..50 {
+            vars = vars.set(AdjVar::RcSpeedPitch, 1);
+        }
+        let cmd = OutgoingCommand::from_command(&vars).unwrap();
+        assert_eq!(cmd.id, SetAdjVarsVal::ID);
+        assert_eq!(cmd.payload.len(), 251);
+
+        let vars = vars.set(AdjVar::RcSpeedPitch, 1);
+        let err = OutgoingCommand::from_command(&vars).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let queued: Box<dyn OutgoingCommandObj> = Box::new(vars);
+        let err = queued.to_outgoing().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn array_ranges_check_each_element() {
+        #[derive(Debug, PartialEq, Transmit)]
+        struct Gains {
+            #[range(-100..=100)]
+            gain: [i8; 2],
+            #[range(-720..=720)]
+            angles: [i16; 3],
+        }
+
+        assert_eq!(
+            round_trip(
+                Gains {
+                    gain: [-100, 100],
+                    angles: [-720, 0, 720],
+                },
+                &[0x9C, 0x64, 0x30, 0xFD, 0x00, 0x00, 0xD0, 0x02]
+            ),
+            Gains {
+                gain: [-100, 100],
+                angles: [-720, 0, 720],
+            }
+        );
+
+        let err = Gains::from_slice(&[0x00, 0x9B, 0, 0, 0, 0, 0, 0]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "data outside of valid range: Gains.gain[1] is -101"
+        );
+        let err = Gains {
+            gain: [0, 0],
+            angles: [-721, 0, 721],
+        }
+        .validate()
+        .unwrap_err();
+        assert!(err.to_string().ends_with("Gains.angles[0] is -721"));
+    }
+
+    #[test]
+    fn adj_vars_cfg_slots() {
+        let mut payload = vec![0; 128];
+        payload[..6].copy_from_slice(&[0x42, 1, 2, 3, 4, 5]); // serial channel 2
+        payload[60..64].copy_from_slice(&[0x03, AdjVar::RcSpeedPitch.id(), 10, 200]); // PWM 3
+        let cfg = match IncomingCommand::from_payload(ReadAdjVarsCfg::ID, &payload).unwrap() {
+            IncomingCommand::ReadAdjVarsCfg(cfg) => cfg,
+            other => panic!("unexpected command {:?}", other),
+        };
+        assert_eq!(cfg.trigger_slots[0].source, RcSource::Serial(2));
+        assert_eq!(cfg.trigger_slots[0].actions, [1, 2, 3, 4, 5]);
+        assert_eq!(cfg.trigger_slots[1].source, RcSource::None);
+        assert_eq!(cfg.analog_slots[0].source, RcSource::Pwm(3));
+        assert_eq!(cfg.analog_slots[0].var(), Some(AdjVar::RcSpeedPitch));
+        assert_eq!(
+            (cfg.analog_slots[0].min, cfg.analog_slots[0].max),
+            (10, 200)
+        );
+
+        let mut write = WriteAdjVarsCfg::from(&cfg);
+        assert_eq!(write.to_vec().unwrap(), payload);
+        write.analog_slots[1].source = RcSource::Pwm(9);
+        assert!(OutgoingCommand::from_command(&write).is_err());
+
+        // Variables added by newer firmware still decode and are written back unchanged
+        payload[61] = 0xF0;
+        let cfg = ReadAdjVarsCfg::from_slice(&payload).unwrap();
+        assert_eq!(cfg.analog_slots[0].var_id, 0xF0);
+        assert_eq!(cfg.analog_slots[0].var(), None);
+        assert_eq!(WriteAdjVarsCfg::from(&cfg).to_vec().unwrap(), payload);
+
+        let mut slot = cfg.analog_slots[0].clone();
+        slot.set_var(AdjVar::GyroTrust);
+        assert_eq!(slot.var_id, 36);
+    }
+
+    #[test]
+    fn u24_boundaries() {
+        assert_eq!(round_trip(U24(0), &[0, 0, 0]), U24(0));
+        assert_eq!(
+            round_trip(U24(0x12_3456), &[0x56, 0x34, 0x12]),
+            U24(0x12_3456)
+        );
+        assert_eq!(
+            round_trip(U24(U24::MAX), &[0xFF, 0xFF, 0xFF]),
+            U24(U24::MAX)
+        );
+        assert!(U24(U24::MAX + 1).to_writer(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn i24_sign_boundary() {
+        assert_eq!(round_trip(I24(-1), &[0xFF, 0xFF, 0xFF]), I24(-1));
+        assert_eq!(
+            round_trip(I24(I24::MAX), &[0xFF, 0xFF, 0x7F]),
+            I24(I24::MAX)
+        );
+        assert_eq!(
+            round_trip(I24(I24::MIN), &[0x00, 0x00, 0x80]),
+            I24(I24::MIN)
+        );
+        assert!(I24(I24::MAX + 1).to_writer(&mut Vec::new()).is_err());
+        assert!(I24(I24::MIN - 1).to_writer(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn byte_arrays_match_element_encoding() {
+        let bytes = [1u8, 2, 3, 4, 5];
+        let data = round_trip(bytes, &bytes);
+        assert_eq!(data, bytes);
+        assert!(<[u8; 6]>::from_reader(&mut &bytes[..]).is_err());
+        assert_eq!(round_trip([true, false], &[1, 0]), [true, false]);
+    }
+
+    #[test]
+    fn cmd_error_decodes_known_and_unknown_codes() {
+        let payload = [6, 0, 0, 0, 0];
+        assert_eq!(
+            IncomingCommand::from_payload(CmdError::ID, &payload).unwrap(),
+            IncomingCommand::CmdError(CmdError {
+                error_code: BoardError::UnknownCommand,
+                error_data: [0; 4],
+            })
+        );
+        assert_eq!(
+            round_trip(BoardError::Unknown(42), &[42]),
+            BoardError::Unknown(42)
+        );
+    }
+
+    #[test]
+    fn millis16_converts_durations() {
+        assert_eq!(
+            Millis16::try_from(Duration::from_micros(20_900)).unwrap(),
+            Millis16(20)
+        );
+        assert_eq!(
+            Duration::from(Millis16(u16::MAX)),
+            Duration::from_millis(65535)
+        );
+        assert!(Millis16::try_from(Duration::from_millis(65536)).is_err());
+        assert_eq!(
+            round_trip(Millis16(0x1234), &[0x34, 0x12]),
+            Millis16(0x1234)
+        );
+    }
+
+    #[test]
+    fn realtime_data_custom_flags_match_fields() {
+        let request = RealtimeDataCustom::new().acc_data().gyro_data();
+        assert_eq!(request.flags.bits(), 1 << 8 | 1 << 4);
+
+        let mut payload = vec![0x10, 0x00]; // timestamp
+        payload.extend_from_slice(&[1, 0, 2, 0, 3, 0]); // gyro_data
+        payload.extend_from_slice(&[0xFF, 0xFF, 0, 0, 1, 0]); // acc_data
+        let data = RealtimeDataCustomResponse::parse(request.flags, &payload).unwrap();
+        assert_eq!(
+            data,
+            RealtimeDataCustomResponse {
+                timestamp_ms: 16,
+                gyro_data: Some([1, 2, 3]),
+                acc_data: Some([-1, 0, 1]),
+                ..Default::default()
+            }
+        );
+    }
+
+    fn read_params3_payload() -> Vec<u8> {
+        let mut payload = vec![1]; // profile_id
+        for axis in 0..3 {
+            payload.extend_from_slice(&[10 + axis, 20, 30, 120, 0, 22]);
+        }
+        payload.push(5); // acc_limiter_all
+        payload.extend_from_slice(&[0xFE, 3]); // ext_fc_gain
+        for _ in 0..3 {
+            payload.extend_from_slice(&(-90i16).to_le_bytes()); // min_angle
+            payload.extend_from_slice(&90i16.to_le_bytes()); // max_angle
+            payload.extend_from_slice(&[0b101, 4, 30, 0]); // mode, lpf, speed, follow
+        }
+        payload.extend_from_slice(&[100, 1, 2, 0]); // gyro_thrust .. serial_speed
+        payload.extend_from_slice(&[0xFF, 0, 1, 5, 10, 0]); // rc_trim .. rc_virt_mode
+        payload.extend_from_slice(&[0x01, 0x02, 0x45, 0x00, 0x03, 0x04]); // rc_map
+        payload.extend_from_slice(&[0, 0]); // rc_mix_fc
+        payload
+    }
+
+    #[test]
+    fn params_3_check_array_ranges() {
+        let mut payload = read_params3_payload();
+        payload[21] = 101; // ext_fc_gain[1]
+        let err = ReadParams3::from_slice(&payload).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("ReadParams3.ext_fc_gain[1] is 101"));
+
+        let mut payload = read_params3_payload();
+        payload[52] = 0x80; // rc_trim[2]
+        let err = ReadParams3::from_slice(&payload).unwrap_err();
+        assert!(err.to_string().ends_with("ReadParams3.rc_trim[2] is -128"));
+
+        let mut params =
+            WriteParams3::from(&ReadParams3::from_slice(&read_params3_payload()).unwrap());
+        params.rc_trim[0] = -128;
+        assert!(params.to_vec().is_err());
+    }
+
+    #[test]
+    fn rc_mapping_sources() {
+        let params = ReadParams3::from_slice(&read_params3_payload()).unwrap();
+        let map = &params.rc_map;
+        assert_eq!(map.axis(Axis::Roll), RcSource::Pwm(1));
+        assert_eq!(map.axis(Axis::Yaw), RcSource::Serial(5));
+        assert_eq!(map.cmd, RcSource::None);
+
+        let mut write = WriteParams3::from(&params);
+        write.rc_map.set_axis(Axis::Pitch, RcSource::Virtual(31));
+        write.rc_map.cmd = RcSource::Analog(3);
+        let written = write.to_vec().unwrap();
+        let reread = ReadParams3::from_slice(&written).unwrap();
+        assert_eq!(reread.rc_map.pitch, RcSource::Virtual(31));
+        assert_eq!(reread.rc_map.cmd, RcSource::Analog(3));
+
+        assert_eq!(
+            round_trip(RcSource::Analog(3), &[0x23]),
+            RcSource::Analog(3)
+        );
+        // Input numbers start from 1 and each type has a limited number of inputs
+        assert!(RcSource::Pwm(6).validate().is_err());
+        assert!(RcSource::Analog(4).to_vec().is_err());
+        assert!(RcSource::from_slice(&[0x20]).is_err());
+        assert!(RcSource::from_slice(&[0x81]).is_err());
+    }
+
+    #[test]
+    fn read_params3_to_write_params3_is_stable() {
+        let payload = read_params3_payload();
+        let read = ReadParams3::parse_payload(&mut &payload[..]).unwrap();
+        let mut write = WriteParams3::from(&read);
+        write.axis[Axis::Pitch].p = 50;
+
+        let mut written = Vec::new();
+        write.write_payload(&mut written).unwrap();
+        let reread = ReadParams3::parse_payload(&mut &written[..]).unwrap();
+        assert_eq!(reread.axis[Axis::Pitch].p, 50);
+        assert_eq!(WriteParams3::from(&reread), write);
+
+        let mut unchanged = Vec::new();
+        WriteParams3::from(&read)
+            .write_payload(&mut unchanged)
+            .unwrap();
+        assert_eq!(unchanged, payload);
+    }
+
+    #[test]
+    fn non_zero_rejects_zero() {
+        assert_eq!(
+            round_trip(NonZeroU16::new(0x0102).unwrap(), &[2, 1]).get(),
+            0x0102
+        );
+        let err = NonZeroU8::from_reader(&mut &[0][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn names_follow_direction() {
+        assert_eq!(command_name(Control::ID), Some("CMD_CONTROL"));
+        assert_eq!(command_name(CmdError::ID), Some("CMD_ERROR"));
+        assert_eq!(command_name(0), None);
+
+        let confirm = IncomingCommand::from_payload(Confirm::ID, &[Control::ID]).unwrap();
+        assert_eq!(confirm.name(), Some("CMD_CONFIRM"));
+    }
+
+    #[test]
+    fn from_command_serializes_payload() {
+        let cmd = OutgoingCommand::from_command(&GetAngles).unwrap();
+        assert_eq!(cmd.id, GetAngles::ID);
+        assert!(cmd.payload.is_empty());
+
+        let cmd = OutgoingCommand::from_command(&CalibCogging {
+            axes: [true, false, true],
+            iterations: 3,
+            _reserved: Reserved,
+        })
+        .unwrap();
+        assert_eq!(cmd.id, CalibCogging::ID);
+        assert_eq!(cmd.payload, [1, 0, 1, 3, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn shared_ids_decode_as_the_response() {
+        let scan = IncomingCommand::from_payload(CanDeviceScan::ID, &[0]).unwrap();
+        assert_eq!(
+            scan,
+            IncomingCommand::CanDeviceScanResponse(CanDeviceScanResponse {
+                devices: CountPrefixed(Vec::new()),
+            })
+        );
+        assert_eq!(scan.id(), CanDeviceScan::ID);
+
+        let angles = IncomingCommand::from_payload(GetAngles::ID, &[0; 18]).unwrap();
+        assert!(matches!(angles, IncomingCommand::GetAnglesResponse(_)));
+        let confirm = IncomingCommand::from_payload(Control::ID, &[Control::ID]).unwrap();
+        assert!(matches!(confirm, IncomingCommand::Confirm(_)));
+    }
+
+    #[test]
+    fn catalog_matches_commands() {
+        assert!(COMMANDS.windows(2).all(|pair| pair[0].id <= pair[1].id));
+
+        fn check<C: Command>(name: &str) {
+            let info = COMMANDS
+                .iter()
+                .find(|info| info.id == C::ID && info.name == name)
+                .unwrap();
+            assert!(!C::DIRECTION.is_outgoing() || info.direction.is_outgoing());
+            assert!(!C::DIRECTION.is_incoming() || info.direction.is_incoming());
+        }
+        check::<BoardInfo>("CMD_BOARD_INFO");
+        check::<Control>("CMD_CONTROL");
+        check::<Confirm>("CMD_CONFIRM");
+        check::<GetAngles>("CMD_GET_ANGLES");
+        check::<GetAnglesResponse>("CMD_GET_ANGLES");
+        check::<RealtimeData4>("CMD_REALTIME_DATA_4");
+        check::<CmdError>("CMD_ERROR");
+
+        let sendable = COMMANDS.iter().filter(|info| info.direction.is_outgoing());
+        assert!(sendable.clone().any(|info| info.name == "CMD_MOTORS_ON"));
+        assert!(!sendable.clone().any(|info| info.name == "CMD_CONFIRM"));
+    }
+
+    #[test]
+    fn rc_follow_zero_is_disabled() {
+        assert_eq!(round_trip(RcFollow::Disabled, &[0]), RcFollow::Disabled);
+        assert_eq!(
+            round_trip(RcFollow::Ratio(-5), &[0xFB]),
+            RcFollow::Ratio(-5)
+        );
+        assert!(RcFollow::Ratio(0).validate().is_err());
+        assert!(RcFollow::from_reader(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn skipped_fields_are_not_sent() {
+        #[derive(Debug, PartialEq, Transmit)]
+        struct Version {
+            raw: u16,
+            #[transmit(skip)]
+            parsed: Option<(u8, u8)>,
+        }
+
+        let version = Version {
+            raw: 2687,
+            parsed: Some((2, 68)),
+        };
+        let read = round_trip(version, &[0x7F, 0x0A]);
+        assert_eq!(
+            read,
+            Version {
+                raw: 2687,
+                parsed: None
+            }
+        );
+    }
+
+    #[test]
+    fn conditional_fields_follow_earlier_fields() {
+        #[derive(Debug, PartialEq, Transmit)]
+        struct Conditional {
+            has_extra: bool,
+            #[transmit(if = "*has_extra")]
+            extra: Option<u16>,
+            last: u8,
+        }
+
+        let with = Conditional {
+            has_extra: true,
+            extra: Some(0x0102),
+            last: 3,
+        };
+        assert_eq!(round_trip(with, &[1, 2, 1, 3]).extra, Some(0x0102));
+        let without = Conditional {
+            has_extra: false,
+            extra: None,
+            last: 3,
+        };
+        assert_eq!(round_trip(without, &[0, 3]).extra, None);
+
+        let mismatched = Conditional {
+            has_extra: false,
+            extra: Some(1),
+            last: 3,
+        };
+        let err = mismatched.to_writer(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn can_driver_states_are_count_prefixed() {
+        let payload = [2, 1, 0b1, 40, 0, 0, 2, 0b11, 0xF6, 0x04, 0x00, 0xAA];
+        let cmd = IncomingCommand::from_payload(RealtimeDataCanDrv::ID, &payload).unwrap();
+        let drivers = match cmd {
+            IncomingCommand::RealtimeDataCanDrv(data) => data.drivers.0,
+            other => panic!("unexpected command {:?}", other),
+        };
+        assert_eq!(drivers.len(), 2);
+        assert_eq!(drivers[1].temperature, -10);
+        assert_eq!(drivers[1].error_flags, 4);
+
+        let too_many = CountPrefixed(vec![0u8; 256]);
+        assert!(too_many.to_writer(&mut Vec::new()).is_err());
+        assert!(IncomingCommand::from_payload(RealtimeDataCanDrv::ID, &payload[..8]).is_err());
+    }
+
+    #[test]
+    fn cogging_curves_have_their_own_lengths() {
+        let payload = [1, 2, 0x10, 0x00, 0xF0, 0xFF, 0, 0, 1, 1, 0x01, 0x00];
+        let info = CoggingCalibInfo::from_reader(&mut &payload[..]).unwrap();
+        assert_eq!(info.axes[0].curve, CountPrefixed(vec![16, -16]));
+        assert!(!info.axes[1].calibrated);
+        assert_eq!(info.axes[2].curve.0.len(), 1);
+
+        let mut written = Vec::new();
+        info.to_writer(&mut written).unwrap();
+        assert_eq!(written, payload);
+    }
+
+    #[test]
+    fn validation_reaches_nested_fields() {
+        let mut params = ReadParams3::from_reader(&mut &read_params3_payload()[..]).unwrap();
+        assert!(params.validate().is_ok());
+        params.rc_status[Axis::Yaw].lpf = 16;
+        assert!(params.validate().is_err());
+        assert!(params.to_writer(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn motor_pid_helpers() {
+        let mut motor = ReadParams3::from_reader(&mut &read_params3_payload()[..])
+            .unwrap()
+            .axis[Axis::Roll]
+            .clone();
+        motor.set_pid(10, 20, 30).unwrap();
+        assert_eq!(motor.pid(), (10, 20, 30));
+        assert_eq!(motor.to_string(), "10/20/30");
+    }
+
+    #[test]
+    fn flags_convert_to_and_from_names() {
+        let features = BoardInfoFeatures::from_bits(0b101).unwrap();
+        let names = features.flag_names();
+        assert_eq!(names.len(), 2);
+        let names: Vec<_> = names.iter().map(String::as_str).collect();
+        assert_eq!(BoardInfoFeatures::from_flag_names(names), Some(features));
+        assert_eq!(BoardInfoFeatures::from_flag_names(vec!["NOT_A_FLAG"]), None);
+        assert!(BoardInfoFeatures::empty().flag_names().is_empty());
+    }
+
+    #[test]
+    fn responses_are_data_or_confirms() {
+        let request = |id| OutgoingCommand {
+            id,
+            sub_id: None,
+            payload: Vec::new(),
+        };
+        assert_eq!(request(ReadParams3::ID).response_id(), Some(21));
+        assert_eq!(request(GetAngles::ID).response_id(), Some(73));
+        assert_eq!(request(64).response_id(), Some(31));
+        assert_eq!(request(WriteParams3::ID).response_id(), Some(Confirm::ID));
+        assert_eq!(request(Control::ID).response_id(), None);
+        assert_eq!(request(0).response_id(), None);
+    }
+
+    fn realtime_data3_payload() -> Vec<u8> {
+        let mut payload = vec![0; 63];
+        payload[14..16].copy_from_slice(&0x0210u16.to_le_bytes()); // system_error
+        payload[55..57].copy_from_slice(&1180u16.to_le_bytes()); // bat_level
+        payload
+    }
+
+    #[test]
+    fn realtime_data_system_errors() {
+        let data = RealtimeData3::from_reader(&mut &realtime_data3_payload()[..]).unwrap();
+        assert!(data.system_error.contains(SystemErrorFlags::PROTECTION));
+        assert!(data.system_error.contains(SystemErrorFlags::MISS_STEPS));
+        assert!(!data.system_error.contains(SystemErrorFlags::LOW_BAT1));
+        assert_eq!(data.bat_level, 1180);
+
+        let mut payload = realtime_data3_payload();
+        payload.extend_from_slice(&[0; 61]);
+        match parse_command_exact(RealtimeData4::ID, &payload) {
+            Ok(IncomingCommand::RealtimeData4(data4)) => assert_eq!(data4.base, data),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn axes_index_per_axis_arrays() {
+        let mut params = ReadParams3::from_reader(&mut &read_params3_payload()[..]).unwrap();
+        params.axis_mut(Axis::Yaw).poles = 42;
+        assert_eq!(params.axis.0[2].poles, 42);
+        assert_eq!(params.axis[Axis::Yaw], params.axis.0[2]);
+        assert_eq!(params.axis(Axis::Yaw).poles, 42);
+        params.rc_status[Axis::Pitch].lpf = 7;
+        assert_eq!(params.rc_status.0[1].lpf, 7);
+        let axes: Vec<_> = params
+            .axes()
+            .map(|(axis, motor)| (axis, motor.poles))
+            .collect();
+        assert_eq!(axes[2], (Axis::Yaw, 42));
+        assert_eq!(axes[0].0, Axis::Roll);
+    }
+
+    #[test]
+    fn reserved_gaps_can_be_mid_struct() {
+        #[derive(Debug, PartialEq, Transmit)]
+        struct Gap {
+            first: u8,
+            _gap: Reserved<2>,
+            last: u16,
+        }
+
+        let gap = Gap::from_reader(&mut &[1, 0xAA, 0xBB, 2, 0][..]).unwrap();
+        assert_eq!((gap.first, gap.last), (1, 2));
+        assert_eq!(round_trip(gap, &[1, 0, 0, 2, 0]).last, 2);
+    }
+
+    #[test]
+    fn confirm_data_is_typed_by_command() {
+        let confirm = |cmd_id, data: &[u8]| Confirm {
+            cmd_id,
+            data: data.to_vec(),
+        };
+        assert_eq!(
+            confirm(WriteParams3::ID, &[2]).typed_data(),
+            ConfirmData::Profile(ProfileId::Index(2))
+        );
+        assert_eq!(
+            confirm(CMD_WRITE_PARAMS, &[1]).typed_data(),
+            ConfirmData::Profile(ProfileId::Index(1))
+        );
+        assert_eq!(command_name(CMD_WRITE_PARAMS), Some("CMD_WRITE_PARAMS"));
+        assert_eq!(confirm(Control::ID, &[]).typed_data(), ConfirmData::None);
+        assert_eq!(
+            confirm(200, &[1, 2]).typed_data(),
+            ConfirmData::Raw(&[1, 2])
+        );
+    }
+
+    #[test]
+    fn unknown_discriminants_land_in_other() {
+        #[derive(Debug, PartialEq, Transmit)]
+        #[repr(u8)]
+        enum Action {
+            First = 1,
+            Fifth = 5,
+            #[transmit(other)]
+            Other(u8),
+        }
+
+        assert_eq!(round_trip(Action::Fifth, &[5]), Action::Fifth);
+        assert_eq!(round_trip(Action::Other(3), &[3]), Action::Other(3));
+        assert!(Action::Other(1).validate().is_err());
+        assert_eq!(round_trip(Action::First, &[1]), Action::First);
+        assert_variants_round_trip(Action::TRANSMIT_VARIANTS);
+    }
+
+    /// Writes and reads back every variant of a derived enum
+    fn assert_variants_round_trip<T: Transmit + PartialEq + fmt::Debug>(variants: &[T]) {
+        assert!(!variants.is_empty());
+        for variant in variants {
+            let bytes = variant.to_vec().unwrap();
+            assert_eq!(&T::from_slice(&bytes).unwrap(), variant);
+        }
+    }
+
+    /// Checks that every byte that isn't one of `variants` fails to read, for enums without an
+    /// `other` variant
+    fn assert_other_bytes_rejected<T: Transmit + fmt::Debug>(variants: &[T]) {
+        let known: Vec<_> = variants.iter().map(|v| v.to_vec().unwrap()).collect();
+        for byte in 0..=u8::MAX {
+            if !known.contains(&vec![byte]) {
+                assert!(
+                    T::from_slice(&[byte]).is_err(),
+                    "{:#04x} was accepted",
+                    byte
+                );
+            }
+        }
+    }
+
+    /// Generates a test for each one byte enum that all of its variants round-trip and that
+    /// every other byte is rejected. New protocol enums should be added here.
+    macro_rules! enum_tests {
+        ($( $test:ident: $ty:ty, )*) => {
+            $(
+                #[test]
+                fn $test() {
+                    assert_variants_round_trip(<$ty>::TRANSMIT_VARIANTS);
+                    assert_other_bytes_rejected(<$ty>::TRANSMIT_VARIANTS);
+                }
+            )*
+        };
+    }
+
+    enum_tests! {
+        pwm_frequency_variants: PWMFrequency,
+        baud_rate_variants: BaudRate,
+        control_mode_variants: ControlMode,
+        corr_curve_type_variants: CorrCurveType,
+        adj_var_variants: AdjVar,
+        imu_variants: Imu,
+        select_imu_action_variants: SelectImuAction,
+    }
+
+    #[test]
+    fn adj_var_lists_agree() {
+        assert_eq!(AdjVar::TRANSMIT_VARIANTS, AdjVar::ALL);
+    }
+
+    #[test]
+    fn rc_mode_variants() {
+        let modes: Vec<_> = [RcModeControl::Angle, RcModeControl::Speed]
+            .iter()
+            .flat_map(|mode| {
+                [false, true].iter().map(move |&inverted| RcMode {
+                    mode: mode.clone(),
+                    inverted,
+                })
+            })
+            .collect();
+        assert_variants_round_trip(&modes);
+        assert!(RcMode::from_slice(&[0b10]).is_err());
+        assert!(RcMode::from_slice(&[0b111]).is_err());
+    }
+
+    #[test]
+    fn profile_id_sentinel() {
+        assert_eq!(round_trip(ProfileId::Current, &[255]), ProfileId::Current);
+        assert_eq!(round_trip(ProfileId::Index(4), &[4]), ProfileId::Index(4));
+        assert!(ProfileId::from_slice(&[5]).is_err());
+        assert!(ProfileId::Index(5).to_vec().is_err());
+    }
 }
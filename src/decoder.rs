@@ -0,0 +1,589 @@
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::task::{Context, Poll};
+
+use crate::commands::IncomingCommand;
+#[cfg(feature = "frame-events")]
+use crate::events::{self, FrameDirection};
+use crate::parser::{check_frame, parse_command, ParseError, Parser, HEADER_LEN};
+
+/// Incrementally decodes frames from bytes as they arrive, without doing any IO itself.
+///
+/// Bytes before a start byte are skipped. When a frame turns out to be corrupt only its start
+/// byte is dropped, so a valid frame starting inside the corrupt one is still found.
+pub struct FrameDecoder<P: Parser> {
+    buf: Vec<u8>,
+    /// Bytes at the front of `buf` that have already been consumed. They are only removed by the
+    /// next `push`, so consuming many frames from one push doesn't shift the buffer each time.
+    consumed: usize,
+    /// Position of the first buffered byte among all the bytes ever pushed
+    offset: u64,
+    max_buffer: usize,
+    handlers: HashMap<u8, Handler>,
+    _parser: PhantomData<P>,
+}
+
+/// Receives the raw payload of a frame, see `FrameDecoder::register_handler`
+pub type Handler = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Default limit on buffered bytes, enough for several maximum size frames
+pub const DEFAULT_MAX_BUFFER: usize = 4096;
+
+impl<P: Parser> FrameDecoder<P> {
+    pub fn new() -> Self {
+        Self::with_max_buffer(DEFAULT_MAX_BUFFER)
+    }
+
+    /// Creates a decoder that buffers at most `max_buffer` bytes.
+    pub fn with_max_buffer(max_buffer: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            consumed: 0,
+            offset: 0,
+            max_buffer,
+            handlers: HashMap::new(),
+            _parser: PhantomData,
+        }
+    }
+
+    /// Passes the payload of every valid frame with the command `id` to `handler` instead of
+    /// decoding it, for commands this crate doesn't know about. Such frames are consumed without
+    /// being returned from `next_frame`. Replaces any handler already registered for `id`.
+    pub fn register_handler<F>(&mut self, id: u8, handler: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.handlers.insert(id, Box::new(handler));
+    }
+
+    /// Removes the handler for `id`, so its frames are decoded again.
+    pub fn unregister_handler(&mut self, id: u8) {
+        self.handlers.remove(&id);
+    }
+
+    /// Adds received bytes to the end of the buffer.
+    ///
+    /// If the buffer grows past its limit, bytes before the first start byte are dropped since
+    /// they can't be part of a frame. If that isn't enough, for example because `next_frame`
+    /// isn't being called, the whole buffer is dropped and `BufferOverflow` is returned.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), ParseError> {
+        self.buf.drain(..self.consumed);
+        self.consumed = 0;
+        self.buf.extend_from_slice(bytes);
+        if self.buf.len() > self.max_buffer {
+            let start = self
+                .pending()
+                .iter()
+                .position(|b| *b == P::START_BYTE)
+                .unwrap_or(self.buf.len());
+            self.discard(start);
+        }
+        if self.buffered() > self.max_buffer {
+            let len = self.buffered();
+            self.discard(len);
+            return Err(ParseError::BufferOverflow(len));
+        }
+        Ok(())
+    }
+
+    /// Decodes the next frame in the buffer. Returns `None` when more bytes are needed, otherwise
+    /// the frame or the reason it couldn't be decoded. Every call that returns `Some` consumes at
+    /// least one byte.
+    pub fn next_frame(&mut self) -> Option<Result<IncomingCommand, ParseError>> {
+        self.next_frame_at().map(|(_, frame)| frame)
+    }
+
+    /// Like `next_frame`, but also returns where the frame started, counting from the first byte
+    /// ever pushed. For errors this is the start byte that was dropped.
+    pub fn next_frame_at(&mut self) -> Option<(u64, Result<IncomingCommand, ParseError>)> {
+        loop {
+            match self.pending().iter().position(|b| *b == P::START_BYTE) {
+                Some(start) => self.discard(start),
+                None => {
+                    self.discard(self.buffered());
+                    return None;
+                }
+            }
+
+            let offset = self.offset;
+            let (id, frame_len) = match check_frame::<P>(self.pending()) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return None,
+                Err(err) => {
+                    #[cfg(feature = "frame-events")]
+                    if let ParseError::InvalidPayloadChecksum = err {
+                        let pending = self.pending();
+                        events::emit(FrameDirection::Received, pending[1], pending[2], false);
+                    }
+                    self.discard(1);
+                    return Some((offset, Err(err)));
+                }
+            };
+            #[cfg(feature = "frame-events")]
+            events::emit(FrameDirection::Received, id, self.pending()[2], true);
+            let start = self.consumed;
+            let payload = &self.buf[start + HEADER_LEN..start + frame_len - P::checksum_len()];
+
+            if let Some(handler) = self.handlers.get_mut(&id) {
+                handler(payload);
+                self.discard(frame_len);
+                continue;
+            }
+
+            let cmd = parse_command(id, payload);
+            self.discard(frame_len);
+            return Some((offset, cmd));
+        }
+    }
+
+    /// Decodes every complete frame in the buffer, in the same way as calling `next_frame` until it
+    /// returns `None`.
+    ///
+    /// Since the decoder does no IO it works the same with async transports: after each read,
+    /// push the bytes and then handle the frames. `FrameStream` does this for a transport that is
+    /// polled for bytes.
+    pub fn frames(&mut self) -> impl Iterator<Item = Result<IncomingCommand, ParseError>> + '_ {
+        std::iter::from_fn(move || self.next_frame())
+    }
+
+    /// Gives up on the frame at the start of the buffer by dropping its start byte, so decoding
+    /// resumes at the next start byte. For frames that stopped arriving part way, which would
+    /// otherwise hold up every frame after them.
+    pub fn abandon_frame(&mut self) {
+        if self.buffered() > 0 {
+            self.discard(1);
+        }
+    }
+
+    /// Number of bytes waiting to be decoded
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.consumed
+    }
+
+    /// Position of the first buffered byte, counting from the first byte ever pushed
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The bytes still to be decoded
+    fn pending(&self) -> &[u8] {
+        &self.buf[self.consumed..]
+    }
+
+    fn discard(&mut self, len: usize) {
+        self.consumed += len;
+        self.offset += len as u64;
+    }
+}
+
+impl<P: Parser> Default for FrameDecoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decodes frames from a transport that is polled for bytes, feeding a `FrameDecoder` as they
+/// arrive.
+///
+/// `read` is polled like `AsyncRead::poll_read`. It fills the buffer and returns how many bytes it
+/// read, with 0 meaning the transport is closed, or returns `Pending` after arranging for the
+/// context's waker to be woken. This keeps the adapter independent of any async runtime:
+/// `poll_next` has the signature of `Stream::poll_next`. Frames can be awaited one at a time with
+/// `while let Some(cmd) = stream.next().await`.
+///
+/// The stream ends when the transport closes, dropping any part of a frame left over. An IO error
+/// is returned as the last item.
+pub struct FrameStream<P: Parser, R> {
+    read: R,
+    decoder: FrameDecoder<P>,
+    /// Set once the transport is closed or failed
+    done: bool,
+}
+
+impl<P, R> FrameStream<P, R>
+where
+    P: Parser,
+    R: FnMut(&mut Context<'_>, &mut [u8]) -> Poll<io::Result<usize>>,
+{
+    pub fn new(read: R) -> Self {
+        Self::with_decoder(read, FrameDecoder::new())
+    }
+
+    /// Creates a stream using `decoder`, for example one with handlers registered.
+    pub fn with_decoder(read: R, decoder: FrameDecoder<P>) -> Self {
+        Self {
+            read,
+            decoder,
+            done: false,
+        }
+    }
+
+    /// Returns the next frame, reading more bytes whenever the decoded ones run out. Returns
+    /// `Pending` only when `read` does.
+    pub fn poll_next(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<IncomingCommand, ParseError>>> {
+        let mut buf = [0; 256];
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Poll::Ready(Some(frame));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            match (self.read)(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => self.done = true,
+                Poll::Ready(Ok(read)) => {
+                    if let Err(err) = self.decoder.push(&buf[..read]) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                }
+                Poll::Ready(Err(err)) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+            }
+        }
+    }
+
+    /// Waits for the next frame, or `None` once the transport is closed.
+    pub async fn next(&mut self) -> Option<Result<IncomingCommand, ParseError>> {
+        std::future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    pub fn into_inner(self) -> (R, FrameDecoder<P>) {
+        (self.read, self.decoder)
+    }
+}
+
+/// Decodes every complete frame in `buf`, returning them along with the bytes after the last one,
+/// which may be the start of a frame still being received. Leading garbage, corrupt frames and
+/// payloads that can't be decoded are skipped, resyncing the same way as `FrameDecoder`. `buf` is
+/// decoded in place without being copied.
+pub fn parse_all<P: Parser>(buf: &[u8]) -> (Vec<IncomingCommand>, &[u8]) {
+    let mut cmds = Vec::new();
+    let mut pos = 0;
+    loop {
+        match buf[pos..].iter().position(|b| *b == P::START_BYTE) {
+            Some(start) => pos += start,
+            None => return (cmds, &[]),
+        }
+        match check_frame::<P>(&buf[pos..]) {
+            Ok(Some((id, frame_len))) => {
+                let payload = &buf[pos + HEADER_LEN..pos + frame_len - P::checksum_len()];
+                cmds.extend(parse_command(id, payload).ok());
+                pos += frame_len;
+            }
+            Ok(None) => return (cmds, &buf[pos..]),
+            // Only the start byte is skipped, so a frame starting inside this one is still found
+            Err(_) => pos += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::OutgoingCommand;
+    use crate::parser::{APIv1, APIv2};
+
+    fn frame<P: Parser>(id: u8, payload: &[u8]) -> Vec<u8> {
+        let cmd = OutgoingCommand {
+            id,
+            sub_id: None,
+            payload: payload.to_vec(),
+        };
+        let mut frame = Vec::new();
+        P::write_cmd(&mut frame, &cmd).unwrap();
+        frame
+    }
+
+    fn unknown(id: u8, payload: &[u8]) -> IncomingCommand {
+        IncomingCommand::Unknown {
+            id,
+            sub_id: None,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn decodes_across_pushes() {
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        let frame = frame::<APIv2>(1, &[1, 2, 3]);
+        decoder.push(&[0x00, 0xFF]).unwrap();
+        decoder.push(&frame[..5]).unwrap();
+        assert!(decoder.next_frame().is_none());
+        decoder.push(&frame[5..]).unwrap();
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap(),
+            unknown(1, &[1, 2, 3])
+        );
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn v1_waits_for_trailing_checksum() {
+        let mut decoder = FrameDecoder::<APIv1>::new();
+        let frame = frame::<APIv1>(1, &[1, 2, 3]);
+        let (last, rest) = frame.split_last().unwrap();
+        for byte in rest {
+            decoder.push(&[*byte]).unwrap();
+            assert!(decoder.next_frame().is_none());
+        }
+        assert_eq!(decoder.buffered(), rest.len());
+        decoder.push(&[*last]).unwrap();
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap(),
+            unknown(1, &[1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn resyncs_after_bad_checksum() {
+        let mut decoder = FrameDecoder::<APIv1>::new();
+        let mut corrupt = frame::<APIv1>(1, &[1, 2, 3]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        decoder.push(&corrupt).unwrap();
+        decoder.push(&frame::<APIv1>(2, &[4])).unwrap();
+
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(ParseError::InvalidPayloadChecksum))
+        ));
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), unknown(2, &[4]));
+    }
+
+    #[test]
+    fn garbage_always_makes_progress() {
+        // Deterministic pseudo-random bytes, salted with start bytes to reach the deeper states
+        let mut state: u32 = 0x1234_5678;
+        let bytes: Vec<u8> = (0..4096)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                match i % 7 {
+                    0 => APIv1::START_BYTE,
+                    1 => APIv2::START_BYTE,
+                    _ => (state >> 16) as u8,
+                }
+            })
+            .collect();
+
+        let mut v1 = FrameDecoder::<APIv1>::new();
+        let mut v2 = FrameDecoder::<APIv2>::new();
+        for chunk in bytes.chunks(13) {
+            v1.push(chunk).unwrap();
+            v2.push(chunk).unwrap();
+            let mut before = v1.buffered();
+            while v1.next_frame().is_some() {
+                assert!(v1.buffered() < before);
+                before = v1.buffered();
+            }
+            let mut before = v2.buffered();
+            while v2.next_frame().is_some() {
+                assert!(v2.buffered() < before);
+                before = v2.buffered();
+            }
+        }
+    }
+
+    #[test]
+    fn endless_garbage_stays_bounded() {
+        let mut decoder = FrameDecoder::<APIv2>::with_max_buffer(64);
+        for _ in 0..1000 {
+            decoder.push(&[0x00; 50]).unwrap();
+            assert!(decoder.buffered() <= 64);
+        }
+
+        // Start bytes can't be dropped without decoding them
+        assert!(matches!(
+            decoder.push(&[APIv2::START_BYTE; 65]),
+            Err(ParseError::BufferOverflow(65))
+        ));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn handlers_take_registered_frames() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        let sink = seen.clone();
+        decoder.register_handler(200, move |payload| {
+            sink.lock().unwrap().push(payload.to_vec())
+        });
+
+        decoder.push(&frame::<APIv2>(200, &[1, 2])).unwrap();
+        decoder.push(&frame::<APIv2>(201, &[3])).unwrap();
+        decoder.push(&frame::<APIv2>(200, &[4])).unwrap();
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), unknown(201, &[3]));
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(*seen.lock().unwrap(), [vec![1, 2], vec![4]]);
+
+        decoder.unregister_handler(200);
+        decoder.push(&frame::<APIv2>(200, &[5])).unwrap();
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), unknown(200, &[5]));
+    }
+
+    #[test]
+    fn frames_drains_complete_frames() {
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        let mut bytes = frame::<APIv2>(1, &[1]);
+        bytes.extend(frame::<APIv2>(2, &[2]));
+        let partial = frame::<APIv2>(3, &[3]);
+        bytes.extend(&partial[..4]);
+
+        decoder.push(&bytes).unwrap();
+        let cmds: Vec<_> = decoder.frames().map(Result::unwrap).collect();
+        assert_eq!(cmds, [unknown(1, &[1]), unknown(2, &[2])]);
+        decoder.push(&partial[4..]).unwrap();
+        assert_eq!(decoder.frames().next().unwrap().unwrap(), unknown(3, &[3]));
+    }
+
+    #[test]
+    fn truncated_frame_is_not_merged_with_the_next() {
+        use crate::commands::RealtimeData3;
+
+        let realtime = |bat_level: u16| {
+            // Start bytes in the sensor readings make false headers to resync past
+            let mut payload = [0; 63];
+            payload[..12].copy_from_slice(&[0x24; 12]);
+            payload[55..57].copy_from_slice(&bat_level.to_le_bytes());
+            frame::<APIv2>(23, &payload)
+        };
+        let mut bytes = realtime(1000)[..20].to_vec();
+        bytes.extend(realtime(1180));
+
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        let mut decoded = Vec::new();
+        for byte in &bytes {
+            decoder.push(&[*byte]).unwrap();
+            decoded.extend(decoder.frames());
+        }
+        let frames: Vec<_> = decoded.into_iter().filter_map(Result::ok).collect();
+        match &frames[..] {
+            [IncomingCommand::RealtimeData3(RealtimeData3 { bat_level, .. })] => {
+                assert_eq!(*bat_level, 1180)
+            }
+            other => panic!("unexpected frames {:?}", other),
+        }
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn stream_decodes_frames_as_bytes_arrive() {
+        use std::future::Future;
+        use std::task::Waker;
+
+        let mut bytes = vec![0x00];
+        bytes.extend(frame::<APIv2>(1, &[1, 2, 3]));
+        bytes.extend(frame::<APIv2>(2, &[]));
+        // Cut off when the transport closes
+        bytes.extend(&frame::<APIv2>(3, &[3])[..4]);
+
+        // Hands out three bytes at a time, with nothing ready in between
+        let mut input = &bytes[..];
+        let mut ready = false;
+        let read = move |cx: &mut Context<'_>, buf: &mut [u8]| {
+            ready = !ready;
+            if !ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = input.len().min(3).min(buf.len());
+            buf[..len].copy_from_slice(&input[..len]);
+            input = &input[len..];
+            Poll::Ready(Ok(len))
+        };
+        let mut stream = FrameStream::<APIv2, _>::new(read);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut pending = 0;
+        let first = loop {
+            match stream.poll_next(&mut cx) {
+                Poll::Ready(frame) => break frame,
+                Poll::Pending => pending += 1,
+            }
+        };
+        assert_eq!(first.unwrap().unwrap(), unknown(1, &[1, 2, 3]));
+        assert!(pending >= 3);
+
+        let mut rest = Vec::new();
+        let mut collect = Box::pin(async {
+            while let Some(frame) = stream.next().await {
+                rest.push(frame.unwrap());
+            }
+        });
+        while collect.as_mut().poll(&mut cx).is_pending() {}
+        drop(collect);
+        assert_eq!(rest, [unknown(2, &[])]);
+    }
+
+    #[test]
+    fn stream_ends_with_a_read_error() {
+        let mut reads = 0;
+        let read = |_: &mut Context<'_>, buf: &mut [u8]| {
+            reads += 1;
+            if reads > 1 {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let frame = frame::<APIv2>(1, &[1]);
+            buf[..frame.len()].copy_from_slice(&frame);
+            Poll::Ready(Ok(frame.len()))
+        };
+        let mut stream = FrameStream::<APIv2, _>::new(read);
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        assert!(matches!(
+            stream.poll_next(&mut cx),
+            Poll::Ready(Some(Ok(_)))
+        ));
+        assert!(matches!(
+            stream.poll_next(&mut cx),
+            Poll::Ready(Some(Err(ParseError::Io(_))))
+        ));
+        assert!(matches!(stream.poll_next(&mut cx), Poll::Ready(None)));
+    }
+
+    #[test]
+    fn parse_all_resyncs_past_garbage_and_corrupt_frames() {
+        // False start bytes in the garbage, including one with a valid looking header
+        let mut buf = vec![0x24, 0x00, 0x24, 0x01, 0x02, 0x03, 0x00];
+        buf.extend(frame::<APIv2>(1, &[1]));
+        let mut corrupt = frame::<APIv2>(2, &[0x24, 2, 2]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        buf.extend(corrupt);
+        buf.extend(frame::<APIv2>(3, &[3]));
+        // A payload that doesn't decode as its command is skipped as well
+        buf.extend(frame::<APIv2>(23, &[0; 4]));
+        buf.extend(frame::<APIv2>(4, &[]));
+
+        let (cmds, rest) = parse_all::<APIv2>(&buf);
+        assert_eq!(cmds, [unknown(1, &[1]), unknown(3, &[3]), unknown(4, &[])]);
+        assert!(rest.is_empty());
+
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        decoder.push(&buf).unwrap();
+        let decoded: Vec<_> = decoder.frames().filter_map(Result::ok).collect();
+        assert_eq!(decoded, cmds);
+    }
+
+    #[test]
+    fn parse_all_returns_leftover() {
+        let mut buf = vec![0x00, 0x11];
+        buf.extend(frame::<APIv1>(1, &[1]));
+        let mut corrupt = frame::<APIv1>(2, &[2]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        buf.extend(corrupt);
+        buf.extend(frame::<APIv1>(3, &[3]));
+        let partial = frame::<APIv1>(4, &[4, 4]);
+        buf.extend(&partial[..5]);
+
+        let (cmds, rest) = parse_all::<APIv1>(&buf);
+        assert_eq!(cmds, [unknown(1, &[1]), unknown(3, &[3])]);
+        assert_eq!(rest, &partial[..5]);
+    }
+}
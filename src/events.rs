@@ -0,0 +1,108 @@
+use std::sync::OnceLock;
+
+use crate::commands::command_name;
+
+/// Which way a frame went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDirection {
+    Received,
+    Sent,
+}
+
+/// A frame read or written with `Parser::read_cmd` or `Parser::write_cmd`, or found by a
+/// `FrameDecoder`. Frames with a bad header checksum aren't reported, since their id and length
+/// can't be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEvent {
+    pub direction: FrameDirection,
+    pub id: u8,
+    /// The specification's name for the command, see `command_name`
+    pub name: Option<&'static str>,
+    /// Length of the payload, including any sub-id
+    pub len: u8,
+    /// Whether the trailing checksum matched. Always true for sent frames.
+    pub checksum_ok: bool,
+}
+
+type FrameHook = Box<dyn Fn(&FrameEvent) + Send + Sync>;
+
+static HOOK: OnceLock<FrameHook> = OnceLock::new();
+
+/// Sets the function every `FrameEvent` is passed to, such as one forwarding them to a logging
+/// framework with `set_frame_hook(|event| tracing::debug!(?event))`.
+///
+/// The hook is called on whichever thread handled the frame, so it should be quick. It can only
+/// be set once, and `false` is returned if it already was.
+pub fn set_frame_hook<F>(hook: F) -> bool
+where
+    F: Fn(&FrameEvent) + Send + Sync + 'static,
+{
+    HOOK.set(Box::new(hook)).is_ok()
+}
+
+pub(crate) fn emit(direction: FrameDirection, id: u8, len: u8, checksum_ok: bool) {
+    if let Some(hook) = HOOK.get() {
+        hook(&FrameEvent {
+            direction,
+            id,
+            name: command_name(id),
+            len,
+            checksum_ok,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::OutgoingCommand;
+    use crate::decoder::FrameDecoder;
+    use crate::parser::{encode_command, APIv2, Parser};
+    use std::sync::Mutex;
+
+    /// Other tests run at the same time, so only frames with this id are looked at
+    const ID: u8 = 233;
+
+    static SEEN: Mutex<Vec<FrameEvent>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn frames_are_reported_to_the_hook() {
+        assert!(set_frame_hook(|event| {
+            if event.id == ID {
+                SEEN.lock().unwrap().push(*event);
+            }
+        }));
+        assert!(!set_frame_hook(|_| {}));
+
+        let cmd = OutgoingCommand {
+            id: ID,
+            sub_id: Some(1),
+            payload: vec![2, 3],
+        };
+        let mut frame = Vec::new();
+        APIv2::write_cmd(&mut frame, &cmd).unwrap();
+        APIv2::read_cmd(&frame[..]).unwrap();
+
+        let mut corrupt = encode_command::<APIv2>(&cmd).unwrap();
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        decoder.push(&corrupt).unwrap();
+        assert!(decoder.next_frame().unwrap().is_err());
+
+        let event = |direction, checksum_ok| FrameEvent {
+            direction,
+            id: ID,
+            name: None,
+            len: 3,
+            checksum_ok,
+        };
+        assert_eq!(
+            *SEEN.lock().unwrap(),
+            [
+                event(FrameDirection::Sent, true),
+                event(FrameDirection::Received, true),
+                event(FrameDirection::Received, false),
+            ]
+        );
+    }
+}
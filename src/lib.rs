@@ -1,7 +1,48 @@
+#![forbid(unsafe_code)]
+
 #[macro_use]
 extern crate simplebgc_api_macros;
 
+mod client;
 mod commands;
-// mod parser;
+mod decoder;
+#[cfg(feature = "frame-events")]
+mod events;
+mod log;
+#[cfg(any(test, feature = "test-util"))]
+mod loopback;
+mod monitor;
+mod parser;
+mod repeater;
+mod stuffing;
 
-pub use commands::Command;
+pub use client::{verify_confirm, Client, ClientError, DEFAULT_RESPONSE_TIMEOUT};
+pub use commands::{
+    command_name, has_sub_id, supports, AdjVar, AdjVarValue, AdjVarsAnalogSlot, AdjVarsTriggerSlot,
+    Axis, AxisAngles, BaudRate, BoardError, BoardInfo, BoardInfo3, BoardInfoConnectionFlags,
+    BoardInfoFeatures, BoardInfoStateFlags, CalibCogging, CanDevice, CanDeviceScan,
+    CanDeviceScanResponse, CanDriverState, CmdError, CoggingAxisInfo, CoggingCalibInfo, Command,
+    CommandInfo, Confirm, ConfirmData, Control, ControlAxis, ControlMode, CorrCurveType,
+    CountPrefixed, DataStreamInterval, DeviceId, Direction, GetAngles, GetAnglesResponse,
+    GimbalSpeed, Imu, Incoming, IncomingCommand, McuId, Millis16, MotorStatus, Outgoing,
+    OutgoingCommand, OutgoingCommandObj, PWMFrequency, PerAxis, ProfileId, RcFollow, RcMapping,
+    RcMode, RcModeControl, RcSource, RcStatus, ReadAdjVarsCfg, ReadParams3, ReadParamsExt,
+    ReadParamsExt2, ReadParamsExt3, RealtimeData3, RealtimeData4, RealtimeDataCanDrv,
+    RealtimeDataCustom, RealtimeDataCustomFlags, RealtimeDataCustomResponse, Remaining, Reserved,
+    SelectImu3, SelectImuAction, SendCorrCurve, SensorData, SetAdjVarsVal, SystemErrorFlags,
+    Transmit, WriteAdjVarsCfg, WriteParams3, COMMANDS, I24, U24,
+};
+pub use decoder::{parse_all, FrameDecoder, FrameStream, Handler, DEFAULT_MAX_BUFFER};
+#[cfg(feature = "frame-events")]
+pub use events::{set_frame_hook, FrameDirection, FrameEvent};
+pub use log::{LogEntry, LogReader, Timestamped};
+#[cfg(any(test, feature = "test-util"))]
+pub use loopback::LoopbackTransport;
+pub use monitor::{CycleTimeStats, RealtimeMonitor, RealtimeSample};
+pub use parser::{
+    describe, detect_version, dump_frame, encode_command, encode_command_into, frame_len,
+    parse_command, parse_command_exact, read_frame_raw, APIv1, APIv2, ParseError, Parser,
+    ProtocolVersion, SliceWriter, WithCrc32, WithStartByte, DETECT_VERSION_LIMIT,
+};
+pub use repeater::ControlRepeater;
+pub use stuffing::{EscapeXor, Passthrough, Unstuff, UnstuffReader};
@@ -0,0 +1,145 @@
+use std::io::Read;
+use std::time::SystemTime;
+
+use crate::commands::IncomingCommand;
+use crate::decoder::FrameDecoder;
+use crate::parser::{ParseError, Parser};
+
+/// A decoded command along with the time it was received, as returned by
+/// `Client::receive_timestamped`. The time comes from the local clock, since the protocol has no
+/// timestamps of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timestamped<T> {
+    pub received_at: SystemTime,
+    pub command: T,
+}
+
+/// A frame decoded from a log, or the reason the bytes at `offset` couldn't be decoded
+#[derive(Debug)]
+pub struct LogEntry {
+    /// Position of the frame's start byte in the log
+    pub offset: u64,
+    pub frame: Result<IncomingCommand, ParseError>,
+}
+
+/// Decodes every frame in a log of received bytes, such as a capture of a serial port, along with
+/// where in the log it started.
+///
+/// Corrupt regions are reported as errors at the offset where each bad frame started, and
+/// decoding carries on with the next start byte after it. Bytes left at the end of the log that
+/// don't make up a whole frame are ignored. An IO error is returned as the last entry.
+pub struct LogReader<R, P: Parser> {
+    reader: R,
+    decoder: FrameDecoder<P>,
+    /// Set once the reader is exhausted or failed
+    done: bool,
+}
+
+impl<R: Read, P: Parser> LogReader<R, P> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: FrameDecoder::new(),
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read, P: Parser> Iterator for LogReader<R, P> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        let mut buf = [0; 1024];
+        loop {
+            if let Some((offset, frame)) = self.decoder.next_frame_at() {
+                return Some(LogEntry { offset, frame });
+            }
+            if self.done {
+                return None;
+            }
+            let read = match self.reader.read(&mut buf) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(read) => read,
+                Err(err) => {
+                    self.done = true;
+                    return Some(LogEntry {
+                        offset: self.decoder.offset() + self.decoder.buffered() as u64,
+                        frame: Err(err.into()),
+                    });
+                }
+            };
+            // The decoder is drained before every push, so this only holds part of one frame
+            // plus the new bytes and can't overflow
+            if let Err(err) = self.decoder.push(&buf[..read]) {
+                return Some(LogEntry {
+                    offset: self.decoder.offset(),
+                    frame: Err(err),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::OutgoingCommand;
+    use crate::parser::{encode_command, APIv2};
+
+    fn frame(id: u8, payload: &[u8]) -> Vec<u8> {
+        encode_command::<APIv2>(&OutgoingCommand {
+            id,
+            sub_id: None,
+            payload: payload.to_vec(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_offsets_and_resyncs() {
+        let mut log = vec![0x00, 0x11];
+        log.extend(frame(200, &[1, 2]));
+        let corrupt_at = log.len() as u64;
+        let mut corrupt = frame(201, &[3]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        log.extend(corrupt);
+        let last_at = log.len() as u64;
+        log.extend(frame(202, &[4]));
+        // Spread over many reads so frames straddle them
+        log.extend([0x00; 4000]);
+        let end_at = log.len() as u64;
+        log.extend(frame(204, &[]));
+        log.extend(&frame(203, &[5])[..3]);
+
+        let entries: Vec<_> = LogReader::<_, APIv2>::new(&log[..]).collect();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.offset,
+                    entry.frame.as_ref().ok().map(IncomingCommand::id),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, Some(200)),
+                (corrupt_at, None),
+                (last_at, Some(202)),
+                (end_at, Some(204)),
+            ]
+        );
+        assert!(matches!(
+            entries[1].frame,
+            Err(ParseError::InvalidPayloadChecksum)
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// A transport that reads back whatever was written to it, for testing without a board.
+///
+/// Reads time out once everything written has been read, the way a quiet serial port would.
+/// Bytes can also be injected as if the board had sent them, and bytes waiting to be read can be
+/// corrupted to exercise resyncing.
+#[derive(Debug, Default)]
+pub struct LoopbackTransport {
+    pending: VecDeque<u8>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `bytes` to be read after anything already waiting.
+    pub fn inject(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes);
+    }
+
+    /// Flips the bits of the waiting byte at `index`, returning whether there was such a byte.
+    pub fn corrupt(&mut self, index: usize) -> bool {
+        match self.pending.get_mut(index) {
+            Some(byte) => {
+                *byte ^= 0xFF;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of bytes waiting to be read
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl Read for LoopbackTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() && !buf.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let len = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for LoopbackTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
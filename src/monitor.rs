@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use crate::commands::{Axis, IncomingCommand, RealtimeData3, SystemErrorFlags};
+
+/// Power a motor reports when it's driven as hard as it can be
+const FULL_POWER: u8 = 255;
+
+/// Main loop time statistics over the most recent frames, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CycleTimeStats {
+    pub min: u16,
+    pub max: u16,
+    pub mean: f64,
+    /// Number of frames the statistics cover, at most the monitor's window
+    pub frames: usize,
+}
+
+/// A realtime frame along with what `RealtimeMonitor` derived from it and the frames before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RealtimeSample {
+    /// The frame, or the `CMD_REALTIME_DATA_3` part of a `CMD_REALTIME_DATA_4`
+    pub data: RealtimeData3,
+    pub cycle_time: CycleTimeStats,
+    /// Errors set in this frame that weren't set in the previous one
+    pub errors_raised: SystemErrorFlags,
+    /// Errors set in the previous frame that aren't set in this one
+    pub errors_cleared: SystemErrorFlags,
+    /// Whether each motor is at full power, in axis order
+    pub saturated: [bool; 3],
+}
+
+impl RealtimeSample {
+    pub fn errors_changed(&self) -> bool {
+        !self.errors_raised.is_empty() || !self.errors_cleared.is_empty()
+    }
+
+    /// The axes whose motors are at full power
+    pub fn saturated_axes(&self) -> impl Iterator<Item = Axis> + '_ {
+        Axis::zip(&self.saturated)
+            .filter(|(_, saturated)| **saturated)
+            .map(|(axis, _)| axis)
+    }
+}
+
+/// Turns a stream of received commands into `RealtimeSample`s, skipping everything that isn't
+/// `CMD_REALTIME_DATA_3` or `CMD_REALTIME_DATA_4`.
+///
+/// Cycle time statistics cover the last `window` frames. Errors already set in the first frame
+/// are reported as raised. Frames from a `FrameDecoder` can be fed in with
+/// `RealtimeMonitor::new(decoder.frames().filter_map(Result::ok), 50)`.
+pub struct RealtimeMonitor<I> {
+    inner: I,
+    window: usize,
+    cycle_times: VecDeque<u16>,
+    errors: SystemErrorFlags,
+}
+
+impl<I: Iterator<Item = IncomingCommand>> RealtimeMonitor<I> {
+    /// # Panics
+    ///
+    /// If `window` is zero.
+    pub fn new(inner: I, window: usize) -> Self {
+        assert!(window > 0, "window must cover at least one frame");
+        Self {
+            inner,
+            window,
+            cycle_times: VecDeque::with_capacity(window),
+            errors: SystemErrorFlags::empty(),
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    fn sample(&mut self, data: RealtimeData3) -> RealtimeSample {
+        if self.cycle_times.len() == self.window {
+            self.cycle_times.pop_front();
+        }
+        self.cycle_times.push_back(data.cycle_time);
+        let frames = self.cycle_times.len();
+        let cycle_time = CycleTimeStats {
+            min: self.cycle_times.iter().copied().min().unwrap_or_default(),
+            max: self.cycle_times.iter().copied().max().unwrap_or_default(),
+            mean: self.cycle_times.iter().map(|&t| f64::from(t)).sum::<f64>() / frames as f64,
+            frames,
+        };
+
+        let errors_raised = data.system_error - self.errors;
+        let errors_cleared = self.errors - data.system_error;
+        self.errors = data.system_error;
+
+        let saturated = data.motor_power.map(|power| power == FULL_POWER);
+        RealtimeSample {
+            data,
+            cycle_time,
+            errors_raised,
+            errors_cleared,
+            saturated,
+        }
+    }
+}
+
+impl<I: Iterator<Item = IncomingCommand>> Iterator for RealtimeMonitor<I> {
+    type Item = RealtimeSample;
+
+    fn next(&mut self) -> Option<RealtimeSample> {
+        loop {
+            let data = match self.inner.next()? {
+                IncomingCommand::RealtimeData3(data) => data,
+                IncomingCommand::RealtimeData4(data) => data.base,
+                _ => continue,
+            };
+            return Some(self.sample(data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Confirm, Transmit};
+
+    fn frame(
+        cycle_time: u16,
+        system_error: SystemErrorFlags,
+        motor_power: [u8; 3],
+    ) -> IncomingCommand {
+        let mut data = RealtimeData3::from_slice(&[0; 63]).unwrap();
+        data.cycle_time = cycle_time;
+        data.system_error = system_error;
+        data.motor_power = motor_power;
+        IncomingCommand::RealtimeData3(data)
+    }
+
+    #[test]
+    fn tracks_cycle_times_errors_and_saturation() {
+        let flag = SystemErrorFlags::PROTECTION;
+        let other = IncomingCommand::Confirm(Confirm {
+            cmd_id: 67,
+            data: Vec::new(),
+        });
+        let frames = vec![
+            frame(800, SystemErrorFlags::empty(), [10, 20, 30]),
+            other,
+            frame(900, flag, [10, 255, 30]),
+            frame(1000, flag, [10, 20, 30]),
+            frame(1300, SystemErrorFlags::empty(), [10, 20, 30]),
+        ];
+        let samples: Vec<_> = RealtimeMonitor::new(frames.into_iter(), 3).collect();
+        assert_eq!(samples.len(), 4);
+
+        assert_eq!(
+            samples[3].cycle_time,
+            CycleTimeStats {
+                min: 900,
+                max: 1300,
+                mean: 1066.6666666666667,
+                frames: 3,
+            }
+        );
+        assert_eq!(samples[1].cycle_time.frames, 2);
+
+        assert!(!samples[0].errors_changed());
+        assert_eq!(samples[1].errors_raised, flag);
+        assert!(!samples[2].errors_changed());
+        assert_eq!(samples[3].errors_cleared, flag);
+
+        assert_eq!(
+            samples[1].saturated_axes().collect::<Vec<_>>(),
+            [Axis::Pitch]
+        );
+        assert_eq!(samples[2].saturated_axes().count(), 0);
+    }
+}
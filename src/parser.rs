@@ -1,67 +1,1026 @@
+use std::borrow::Cow;
 use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
 
-use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
-use crc::{crc16, Hasher16};
+use byteorder::{ReadBytesExt, WriteBytesExt};
 
-use crate::commands::{IncomingCommandS, OutgoingCommand};
+use crate::commands::{command_name, IncomingCommand, OutgoingCommand, Transmit};
+#[cfg(feature = "frame-events")]
+use crate::events::FrameDirection;
 
-pub trait Parser {
-    fn read_cmd<R: ReadBytesExt>(input: R) -> Result<IncomingCommandS, ()>;
-    fn write_cmd<W: WriteBytesExt>(output: W, cmd: OutgoingCommand) -> Result<(), ()>;
+#[derive(Debug)]
+pub enum ParseError {
+    /// Reading or writing failed
+    Io(io::Error),
+    /// The frame was intact but its payload couldn't be decoded into the command `id`
+    InvalidPayload {
+        id: u8,
+        payload: Vec<u8>,
+        source: io::Error,
+    },
+    /// The payload of the command `id` was decoded but `len` bytes were left over
+    TrailingBytes { id: u8, len: usize },
+    /// The header checksum didn't match the id and length
+    InvalidHeaderChecksum,
+    /// The trailing checksum didn't match the payload
+    InvalidPayloadChecksum,
+    /// The payload is longer than the 255 bytes a frame can carry
+    PayloadTooLarge(usize),
+    /// This many bytes were buffered without finding a frame, so they were dropped
+    BufferOverflow(usize),
 }
 
-pub enum APIv1 {}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::InvalidPayload { id, source, .. } => {
+                write!(f, "invalid payload for command {}: {}", Named(*id), source)
+            }
+            Self::TrailingBytes { id, len } => {
+                write!(f, "{} bytes left over after command {}", len, Named(*id))
+            }
+            Self::InvalidHeaderChecksum => write!(f, "invalid header checksum"),
+            Self::InvalidPayloadChecksum => write!(f, "invalid payload checksum"),
+            Self::PayloadTooLarge(len) => {
+                write!(f, "payload of {} bytes does not fit in a frame", len)
+            }
+            Self::BufferOverflow(len) => {
+                write!(f, "dropped {} buffered bytes that contained no frame", len)
+            }
+        }
+    }
+}
 
-const START_BYTE_V1: u8 = 0x3E;
+/// Displays a command id along with its name, if it has one
+struct Named(u8);
 
-impl Parser for APIv1 {
-    fn read_cmd<R: ReadBytesExt>(input: R) -> Result<IncomingCommandS, ()> {
-        todo!();
+impl fmt::Display for Named {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match command_name(self.0) {
+            Some(name) => write!(f, "{} ({})", self.0, name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::InvalidPayload { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A version of the serial protocol's framing. Frames are laid out as the start byte, the id, the
+/// payload length, a header checksum of `(id + len) % 256`, the payload and then a trailing
+/// checksum whose format depends on the version.
+pub trait Parser: Sized {
+    /// The byte every frame starts with
+    const START_BYTE: u8;
+    /// The checksum following the payload
+    type Checksum: Transmit + PartialEq;
+
+    fn checksum(id: u8, len: u8, payload: &[u8]) -> Self::Checksum;
+
+    /// Number of bytes the trailing checksum takes up on the wire
+    fn checksum_len() -> usize {
+        mem::size_of::<Self::Checksum>()
     }
 
-    fn write_cmd<W: WriteBytesExt>(output: W, cmd: OutgoingCommand) -> Result<(), ()> {
-        let len = cmd.payload.len().try_into().unwrap();
-        // TODO: Be safe about IDs. Maybe use num_traits ToPrimitive?
-        let id = cmd.id as u8;
-        let checksum = cmd.payload.iter().fold(0, |acc, x| (acc + x) % 256);
+    /// Reads the next frame. The whole payload is always consumed, even if the command doesn't
+    /// use all of it, so the next read starts at the following frame.
+    fn read_cmd<R: ReadBytesExt>(mut input: R) -> Result<IncomingCommand, ParseError> {
+        let (id, len) = read_header(&mut input, Self::START_BYTE)?;
+        let mut payload = vec![0; len as usize];
+        input.read_exact(&mut payload)?;
+        let checksum_ok =
+            Self::Checksum::from_reader(&mut input)? == Self::checksum(id, len, &payload);
+        #[cfg(feature = "frame-events")]
+        crate::events::emit(FrameDirection::Received, id, len, checksum_ok);
+        if !checksum_ok {
+            return Err(ParseError::InvalidPayloadChecksum);
+        }
 
-        output.write_u8(START_BYTE_V1);
-        output.write_u8(id);
-        output.write_u8(len);
-        output.write_u8((id + len) % 256);
-        output.write(cmd.payload);
-        output.write_u8(checksum);
+        parse_command(id, &payload)
+    }
 
+    /// Writes the whole frame with a single `write_all`, so it isn't split into many small writes
+    /// or interleaved with other writers at the OS level.
+    fn write_cmd<W: WriteBytesExt>(mut output: W, cmd: &OutgoingCommand) -> Result<(), ParseError> {
+        let frame = encode_command::<Self>(cmd)?;
+        output.write_all(&frame)?;
+        #[cfg(feature = "frame-events")]
+        crate::events::emit(FrameDirection::Sent, cmd.id, frame[2], true);
         Ok(())
     }
 }
 
+fn write_frame<P: Parser, W: WriteBytesExt>(
+    output: &mut W,
+    cmd: &OutgoingCommand,
+) -> Result<(), ParseError> {
+    let payload = frame_payload(cmd);
+    let len = payload_len(&payload)?;
+
+    output.write_u8(P::START_BYTE)?;
+    output.write_u8(cmd.id)?;
+    output.write_u8(len)?;
+    output.write_u8(cmd.id.wrapping_add(len))?;
+    output.write_all(&payload)?;
+    P::checksum(cmd.id, len, &payload).to_writer(output)?;
+
+    Ok(())
+}
+
+/// Number of bytes `cmd` takes up on the wire once framed: the start byte, the 3 header bytes, the
+/// payload including any sub-id and the trailing checksum. Useful for sizing buffers or working out
+/// how long a frame takes to send at a given baud rate. Payloads too long to be framed aren't
+/// rejected here, `encode_command` reports those.
+pub fn frame_len<P: Parser>(cmd: &OutgoingCommand) -> usize {
+    let sub_id_len = cmd.sub_id.is_some() as usize;
+    4 + sub_id_len + cmd.payload.len() + P::checksum_len()
+}
+
+/// Frames `cmd` into a new buffer, for transports that don't implement `Write`.
+pub fn encode_command<P: Parser>(cmd: &OutgoingCommand) -> Result<Vec<u8>, ParseError> {
+    let mut frame = Vec::with_capacity(frame_len::<P>(cmd));
+    write_frame::<P, _>(&mut frame, cmd)?;
+    Ok(frame)
+}
+
+/// Frames `cmd` into `buf`, returning the length of the frame. Fails with a `WriteZero` IO error
+/// if `buf` is too small.
+pub fn encode_command_into<P: Parser>(
+    cmd: &OutgoingCommand,
+    buf: &mut [u8],
+) -> Result<usize, ParseError> {
+    let mut sink = SliceWriter::new(buf);
+    write_frame::<P, _>(&mut sink, cmd)?;
+    Ok(sink.written())
+}
+
+/// Writes into a fixed slice, keeping track of how much of it has been filled. Writing past the
+/// end of the slice fails instead of writing part of the bytes.
+pub struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    written: usize,
+}
+
+impl<'a> SliceWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, written: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn written(&self) -> usize {
+        self.written
+    }
+}
+
+impl io::Write for SliceWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let end = self.written + bytes.len();
+        if end > self.buf.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "buffer is full"));
+        }
+        self.buf[self.written..end].copy_from_slice(bytes);
+        self.written = end;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Header is the start byte, the id, the length and the header checksum
+pub(crate) const HEADER_LEN: usize = 4;
+
+/// Checks the checksums of the frame at the start of `buf`, which must begin with a start byte.
+/// Returns the frame's id and length, or `None` if `buf` doesn't hold the whole frame yet.
+pub(crate) fn check_frame<P: Parser>(buf: &[u8]) -> Result<Option<(u8, usize)>, ParseError> {
+    let (id, len, header_checksum) = match buf {
+        [_, id, len, header_checksum, ..] => (*id, *len, *header_checksum),
+        _ => return Ok(None),
+    };
+    if header_checksum != id.wrapping_add(len) {
+        return Err(ParseError::InvalidHeaderChecksum);
+    }
+
+    let payload_end = HEADER_LEN + len as usize;
+    let frame_len = payload_end + P::checksum_len();
+    if buf.len() < frame_len {
+        return Ok(None);
+    }
+    let checksum = P::Checksum::from_reader(&mut &buf[payload_end..frame_len])?;
+    if checksum != P::checksum(id, len, &buf[HEADER_LEN..payload_end]) {
+        return Err(ParseError::InvalidPayloadChecksum);
+    }
+    Ok(Some((id, frame_len)))
+}
+
+/// Finds the next frame in `input` and checks its checksums without decoding the payload, for
+/// measuring link quality cheaply. Returns the id and payload and advances `input` past the
+/// frame. Bytes before the frame are skipped, and after a checksum error `input` is advanced
+/// just past the bad frame's start byte so the next call resyncs. A frame cut off by the end of
+/// `input` is an `UnexpectedEof` IO error and nothing is consumed.
+pub fn read_frame_raw<'a, P: Parser>(input: &mut &'a [u8]) -> Result<(u8, &'a [u8]), ParseError> {
+    let buf: &'a [u8] = input;
+    let start = buf.iter().position(|b| *b == P::START_BYTE);
+    let buf = &buf[start.unwrap_or(buf.len())..];
+    match check_frame::<P>(buf) {
+        Ok(Some((id, frame_len))) => {
+            *input = &buf[frame_len..];
+            Ok((id, &buf[HEADER_LEN..frame_len - P::checksum_len()]))
+        }
+        Ok(None) => {
+            *input = buf;
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        }
+        Err(err) => {
+            *input = &buf[1..];
+            Err(err)
+        }
+    }
+}
+
+/// Skips bytes until `start_byte` and then reads the id and length, checking the header checksum.
+fn read_header<R: ReadBytesExt>(input: &mut R, start_byte: u8) -> Result<(u8, u8), ParseError> {
+    while input.read_u8()? != start_byte {}
+    let id = input.read_u8()?;
+    let len = input.read_u8()?;
+    if input.read_u8()? != id.wrapping_add(len) {
+        return Err(ParseError::InvalidHeaderChecksum);
+    }
+    Ok((id, len))
+}
+
+/// Decodes the payload of the command `id` without any framing, for example when replaying a log
+/// of already deframed commands.
+pub fn parse_command(id: u8, payload: &[u8]) -> Result<IncomingCommand, ParseError> {
+    IncomingCommand::from_payload(id, payload).map_err(|source| ParseError::InvalidPayload {
+        id,
+        payload: payload.to_vec(),
+        source,
+    })
+}
+
+/// Like `parse_command`, but the command must use the whole payload. Leftover bytes are a
+/// `TrailingBytes` error instead of being ignored, which helps when checking new command
+/// definitions against captured frames.
+pub fn parse_command_exact(id: u8, payload: &[u8]) -> Result<IncomingCommand, ParseError> {
+    match IncomingCommand::from_payload_used(id, payload) {
+        Ok((cmd, used)) if used == payload.len() => Ok(cmd),
+        Ok((_, used)) => Err(ParseError::TrailingBytes {
+            id,
+            len: payload.len() - used,
+        }),
+        Err(source) => Err(ParseError::InvalidPayload {
+            id,
+            payload: payload.to_vec(),
+            source,
+        }),
+    }
+}
+
+/// The bytes sent as the frame's payload, which includes the sub-id if the command has one
+fn frame_payload(cmd: &OutgoingCommand) -> Cow<'_, [u8]> {
+    match cmd.sub_id {
+        Some(sub_id) => {
+            let mut payload = Vec::with_capacity(1 + cmd.payload.len());
+            payload.push(sub_id);
+            payload.extend_from_slice(&cmd.payload);
+            Cow::Owned(payload)
+        }
+        None => Cow::Borrowed(&cmd.payload),
+    }
+}
+
+fn payload_len(payload: &[u8]) -> Result<u8, ParseError> {
+    payload
+        .len()
+        .try_into()
+        .map_err(|_| ParseError::PayloadTooLarge(payload.len()))
+}
+
+pub enum APIv1 {}
+
+impl Parser for APIv1 {
+    const START_BYTE: u8 = 0x3E;
+    type Checksum = u8;
+
+    /// Sum of all payload bytes modulo 256
+    fn checksum(_id: u8, _len: u8, payload: &[u8]) -> u8 {
+        payload.iter().fold(0u8, |acc, x| acc.wrapping_add(*x))
+    }
+}
+
 pub enum APIv2 {}
 
-const START_BYTE_V2: u8 = 0x24;
+/// CRC16 as computed by the reference implementation: polynomial 0x8005, data bits fed in least
+/// significant first, zero initial value and no final XOR.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        for bit in 0..8 {
+            let data_bit = (byte >> bit) & 1 == 1;
+            let crc_bit = crc >> 15 == 1;
+            crc <<= 1;
+            if data_bit != crc_bit {
+                crc ^= 0x8005;
+            }
+        }
+    }
+    crc
+}
 
 impl Parser for APIv2 {
-    fn read_cmd<R: ReadBytesExt>(input: R) -> Result<IncomingCommandS, ()> {
-        todo!();
+    const START_BYTE: u8 = 0x24;
+    type Checksum = u16;
+
+    /// CRC16 over the header (excluding the start byte) and the payload
+    fn checksum(id: u8, len: u8, payload: &[u8]) -> u16 {
+        let mut frame = Vec::with_capacity(3 + payload.len());
+        frame.extend_from_slice(&[id, len, id.wrapping_add(len)]);
+        frame.extend_from_slice(payload);
+        crc16(&frame)
+    }
+}
+
+/// The framing of `P` with a different start byte, for firmware forks that changed it. For
+/// example `WithStartByte<APIv2, 0x40>` frames like v2 but starts frames with `0x40`.
+pub struct WithStartByte<P, const START: u8>(PhantomData<P>);
+
+impl<P: Parser, const START: u8> Parser for WithStartByte<P, START> {
+    const START_BYTE: u8 = START;
+    type Checksum = P::Checksum;
+
+    fn checksum(id: u8, len: u8, payload: &[u8]) -> Self::Checksum {
+        P::checksum(id, len, payload)
+    }
+
+    fn checksum_len() -> usize {
+        P::checksum_len()
+    }
+}
+
+/// CRC-32 as used by Ethernet and zlib: reflected polynomial 0xEDB88320, all-ones initial value
+/// and final XOR.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
     }
+    !crc
+}
+
+/// The framing of `P` followed by a CRC-32 over the whole inner frame, from its start byte to
+/// its checksum, for links that wrap each tunneled frame in an integrity field of their own. The
+/// CRC is written little-endian like the rest of the protocol. `WithCrc32<APIv2>` reads and
+/// writes frames as `24 ... <crc16> <crc32>`.
+///
+/// A frame is rejected with `InvalidPayloadChecksum` if either check fails.
+pub struct WithCrc32<P>(PhantomData<P>);
+
+impl<P: Parser> Parser for WithCrc32<P> {
+    const START_BYTE: u8 = P::START_BYTE;
+    type Checksum = (P::Checksum, u32);
+
+    fn checksum(id: u8, len: u8, payload: &[u8]) -> Self::Checksum {
+        let inner = P::checksum(id, len, payload);
+        let mut frame = Vec::with_capacity(4 + payload.len() + P::checksum_len());
+        frame.extend_from_slice(&[P::START_BYTE, id, len, id.wrapping_add(len)]);
+        frame.extend_from_slice(payload);
+        // Writing a checksum into a `Vec` can't fail
+        inner
+            .to_writer(&mut frame)
+            .expect("checksum couldn't be written");
+        (inner, crc32(&frame))
+    }
+
+    fn checksum_len() -> usize {
+        P::checksum_len() + 4
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+}
+
+/// How many bytes `detect_version` reads before giving up
+pub const DETECT_VERSION_LIMIT: usize = 1024;
 
-    fn write_cmd<W: WriteBytesExt>(output: W, cmd: OutgoingCommand) -> Result<(), ()> {
-        let len = cmd.payload.len().try_into().unwrap();
-        // TODO: Be safe about IDs
-        let id = cmd.id as u8;
-        let checksum = {
-            let mut digest = crc16::Digest::new(0x8005);
-            digest.write(cmd.payload);
-            digest.sum16()
+/// Reads from `reader` until a frame header with a valid checksum shows which protocol version
+/// the board speaks. Gives up with `None` after `DETECT_VERSION_LIMIT` bytes or on any read error.
+///
+/// The bytes read are consumed, so the header that was found is lost with them.
+pub fn detect_version<R: io::Read>(reader: &mut R) -> Option<ProtocolVersion> {
+    let mut header = [0; 4];
+    for read in 0..DETECT_VERSION_LIMIT {
+        header.rotate_left(1);
+        header[3] = reader.read_u8().ok()?;
+        if read < 3 || header[3] != header[1].wrapping_add(header[2]) {
+            continue;
+        }
+        match header[0] {
+            APIv1::START_BYTE => return Some(ProtocolVersion::V1),
+            APIv2::START_BYTE => return Some(ProtocolVersion::V2),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<_> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    hex.join(" ")
+}
+
+/// Renders a decoded command as a multi-line report for monitoring tools: its name and id, then
+/// each field on a line of its own, followed by values in real units where the command has them.
+/// Commands this crate can't decode have their payload hex dumped.
+pub fn describe(cmd: &IncomingCommand) -> String {
+    let mut out = match cmd.name() {
+        Some(name) => format!("{} ({})\n", name, cmd.id()),
+        None => format!("unknown command ({})\n", cmd.id()),
+    };
+    if let Some(typed) = cmd.typed() {
+        out += &format!("{:#?}\n", typed);
+    }
+    match cmd {
+        IncomingCommand::Unknown {
+            sub_id, payload, ..
+        } => {
+            if let Some(sub_id) = sub_id {
+                out += &format!("sub-id:  {}\n", sub_id);
+            }
+            out += &format!("payload: {} bytes\n", payload.len());
+            for (i, line) in payload.chunks(16).enumerate() {
+                out += &format!("  {:04X}  {}\n", i * 16, hex(line));
+            }
+        }
+        IncomingCommand::Confirm(confirm) => {
+            let name = command_name(confirm.cmd_id).unwrap_or("unknown command");
+            out += &format!("confirms: {} ({})\n", name, confirm.cmd_id);
+        }
+        IncomingCommand::RealtimeData3(data) => {
+            out += &format!("battery:  {:.2} V\n", data.battery_volts());
+        }
+        IncomingCommand::RealtimeData4(data) => {
+            out += &format!("battery:  {:.2} V\n", data.battery_volts());
+            out += &format!("current:  {:.3} A\n", data.current_amps());
+            out += &format!("power:    {:.2} W\n", data.power_watts());
+        }
+        _ => {}
+    }
+    out
+}
+
+/// Renders a raw frame with each of its parts labelled and its checksums verified, for debugging
+/// frames that fail to parse. Truncated frames are dumped as far as they go.
+pub fn dump_frame(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (&start, rest) = match bytes.split_first() {
+        Some(split) => split,
+        None => return "(empty)\n".to_string(),
+    };
+    let version = match start {
+        APIv1::START_BYTE => "v1",
+        APIv2::START_BYTE => "v2",
+        _ => "unknown start byte",
+    };
+    out += &format!("start:    {:02X} ({})\n", start, version);
+
+    let (id, len, header_checksum, rest) = match rest {
+        [id, len, header_checksum, rest @ ..] => (*id, *len, *header_checksum, rest),
+        _ => {
+            out += &format!("header:   {} (truncated)\n", hex(rest));
+            return out;
+        }
+    };
+    let header_status = if header_checksum == id.wrapping_add(len) {
+        "ok"
+    } else {
+        "BAD"
+    };
+    out += &format!("id:       {:02X} ({})\n", id, id);
+    out += &format!("len:      {:02X} ({})\n", len, len);
+    out += &format!("hdr csum: {:02X} ({})\n", header_checksum, header_status);
+
+    if rest.len() < len as usize {
+        out += &format!("payload:  {} (truncated)\n", hex(rest));
+        return out;
+    }
+    let (payload, rest) = rest.split_at(len as usize);
+    out += &format!("payload:  {}\n", hex(payload));
+
+    let (checksum_ok, checksum_len) = match start {
+        APIv2::START_BYTE => {
+            let checksum = APIv2::checksum(id, len, payload).to_le_bytes();
+            (rest.starts_with(&checksum), APIv2::checksum_len())
+        }
+        _ => (
+            rest.first() == Some(&APIv1::checksum(id, len, payload)),
+            APIv1::checksum_len(),
+        ),
+    };
+    if rest.len() < checksum_len {
+        out += &format!("checksum: {} (truncated)\n", hex(rest));
+        return out;
+    }
+    let (checksum, rest) = rest.split_at(checksum_len);
+    let status = if checksum_ok { "ok" } else { "BAD" };
+    out += &format!("checksum: {} ({})\n", hex(checksum), status);
+    if !rest.is_empty() {
+        out += &format!("trailing: {}\n", hex(rest));
+    }
+    out
+}
+
+#[cfg(test)]
+mod golden;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board_info_payload() -> Vec<u8> {
+        let mut payload = vec![3]; // board_ver
+        payload.extend_from_slice(&2305u16.to_le_bytes()); // firmware_ver
+        payload.push(0b10000); // state_flags1
+        payload.extend_from_slice(&0b000101u16.to_le_bytes()); // board_features
+        payload.push(0b1); // connection_flag
+        payload.extend_from_slice(&[0; 8]); // frw_extra_id
+        payload.extend_from_slice(&[0; 7]); // reserved
+        payload
+    }
+
+    #[test]
+    fn read_typed_command() {
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
         };
+        let mut frame = Vec::new();
+        APIv2::write_cmd(&mut frame, &cmd).unwrap();
 
-        output.write_u8(START_BYTE_V2);
-        output.write_u8(id);
-        output.write_u8(len);
-        output.write_u8((id + len) % 256);
-        output.write(cmd.payload);
-        output.write_u16::<LittleEndian>(checksum);
+        match APIv2::read_cmd(&frame[..]).unwrap() {
+            IncomingCommand::BoardInfo(info) => {
+                assert_eq!(info.board_ver, 3);
+                assert_eq!(info.firmware_ver, 2305);
+            }
+            other => panic!("expected BoardInfo, got {:?}", other),
+        }
+    }
 
-        Ok(())
+    #[test]
+    fn short_payload_propagates_io_error() {
+        // A well-formed frame whose payload is too short to hold a BoardInfo
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload()[..4].to_vec(),
+        };
+        let mut frame = Vec::new();
+        APIv1::write_cmd(&mut frame, &cmd).unwrap();
+
+        match APIv1::read_cmd(&frame[..]) {
+            Err(ParseError::InvalidPayload {
+                id,
+                payload,
+                source,
+                ..
+            }) => {
+                assert_eq!(id, 86);
+                assert_eq!(payload, cmd.payload);
+                assert_eq!(source.kind(), io::ErrorKind::UnexpectedEof);
+            }
+            other => panic!("expected InvalidPayload, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sub_id_round_trip() {
+        let cmd = OutgoingCommand {
+            id: 110,
+            sub_id: Some(7),
+            payload: vec![1, 2, 3],
+        };
+        let mut frame = Vec::new();
+        APIv2::write_cmd(&mut frame, &cmd).unwrap();
+        assert_eq!(frame[2], 4);
+
+        assert_eq!(
+            APIv2::read_cmd(&frame[..]).unwrap(),
+            IncomingCommand::Unknown {
+                id: 110,
+                sub_id: Some(7),
+                payload: vec![1, 2, 3],
+            }
+        );
+    }
+
+    #[test]
+    fn dump_annotates_frame() {
+        let frame = [0x3E, 0x56, 0x02, 0x58, 0x01, 0x02, 0x03];
+        assert_eq!(
+            dump_frame(&frame),
+            "start:    3E (v1)\n\
+             id:       56 (86)\n\
+             len:      02 (2)\n\
+             hdr csum: 58 (ok)\n\
+             payload:  01 02\n\
+             checksum: 03 (ok)\n"
+        );
+        assert!(dump_frame(&frame[..5]).ends_with("payload:  01 (truncated)\n"));
+    }
+
+    #[test]
+    fn describe_lists_fields_and_dumps_unknown_payloads() {
+        let unknown = IncomingCommand::Unknown {
+            id: 200,
+            sub_id: Some(7),
+            payload: (0..18).collect(),
+        };
+        assert_eq!(
+            describe(&unknown),
+            "unknown command (200)\n\
+             sub-id:  7\n\
+             payload: 18 bytes\n  \
+             0000  00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F\n  \
+             0010  10 11\n"
+        );
+
+        let confirm = parse_command(67, &[22]).unwrap();
+        assert_eq!(
+            describe(&confirm),
+            "CMD_CONFIRM (67)\n\
+             Confirm {\n    \
+                 cmd_id: 22,\n    \
+                 data: [],\n\
+             }\n\
+             confirms: CMD_WRITE_PARAMS_3 (22)\n"
+        );
+
+        let mut payload = vec![0; 63];
+        payload[55..57].copy_from_slice(&1180u16.to_le_bytes());
+        let report = describe(&parse_command(23, &payload).unwrap());
+        assert!(report.starts_with("CMD_REALTIME_DATA_3 (23)\nRealtimeData3 {\n"));
+        assert!(report.contains("\n    bat_level: 1180,\n"));
+        assert!(report.ends_with("battery:  11.80 V\n"));
+    }
+
+    #[test]
+    fn parse_command_from_slice() {
+        match parse_command(86, &board_info_payload()).unwrap() {
+            IncomingCommand::BoardInfo(info) => assert_eq!(info.firmware_ver, 2305),
+            other => panic!("expected BoardInfo, got {:?}", other),
+        }
+        assert!(matches!(
+            parse_command(86, &[]),
+            Err(ParseError::InvalidPayload { id: 86, .. })
+        ));
+    }
+
+    #[test]
+    fn encode_command_frames_into_vec() {
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        };
+        let frame = encode_command::<APIv1>(&cmd).unwrap();
+        assert_eq!(frame[0], APIv1::START_BYTE);
+        assert_eq!(frame.len(), 4 + cmd.payload.len() + 1);
+        assert_eq!(
+            APIv1::read_cmd(&frame[..]).unwrap(),
+            parse_command(86, &cmd.payload).unwrap()
+        );
+    }
+
+    #[test]
+    fn trailing_payload_bytes_are_skipped() {
+        let mut payload = vec![0; 18];
+        payload[0] = 42; // roll imu_angle
+        payload.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        let mut frames = encode_command::<APIv2>(&OutgoingCommand {
+            id: 73,
+            sub_id: None,
+            payload,
+        })
+        .unwrap();
+        frames.extend(
+            encode_command::<APIv2>(&OutgoingCommand {
+                id: 1,
+                sub_id: None,
+                payload: vec![7],
+            })
+            .unwrap(),
+        );
+
+        let mut input = &frames[..];
+        match APIv2::read_cmd(&mut input).unwrap() {
+            IncomingCommand::GetAnglesResponse(angles) => {
+                assert_eq!(angles.axes[crate::commands::Axis::Roll].imu_angle, 42)
+            }
+            other => panic!("expected GetAnglesResponse, got {:?}", other),
+        }
+        assert_eq!(APIv2::read_cmd(&mut input).unwrap().id(), 1);
+    }
+
+    #[test]
+    fn oversized_payload_is_an_error() {
+        let cmd = OutgoingCommand {
+            id: 1,
+            sub_id: None,
+            payload: vec![0; 256],
+        };
+        assert!(matches!(
+            encode_command::<APIv1>(&cmd),
+            Err(ParseError::PayloadTooLarge(256))
+        ));
+
+        // The sub-id counts towards the length too
+        let cmd = OutgoingCommand {
+            id: 110,
+            sub_id: Some(1),
+            payload: vec![0; 255],
+        };
+        assert!(matches!(
+            encode_command::<APIv2>(&cmd),
+            Err(ParseError::PayloadTooLarge(256))
+        ));
+    }
+
+    #[test]
+    fn header_checksum_wraps() {
+        let cmd = OutgoingCommand {
+            id: 200,
+            sub_id: None,
+            payload: vec![0xFF; 100],
+        };
+        let frame = encode_command::<APIv1>(&cmd).unwrap();
+        assert_eq!(frame[3], 44);
+        assert_eq!(APIv1::read_cmd(&frame[..]).unwrap().id(), 200);
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let frame = encode_command::<APIv2>(&OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        })
+        .unwrap();
+        for len in 0..frame.len() {
+            match APIv2::read_cmd(&frame[..len]) {
+                Err(ParseError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+                other => panic!("expected an IO error, got {:?}", other),
+            }
+            dump_frame(&frame[..len]);
+        }
+    }
+
+    #[test]
+    fn corrupt_header_is_an_error() {
+        let mut frame = encode_command::<APIv1>(&OutgoingCommand {
+            id: 1,
+            sub_id: None,
+            payload: vec![1],
+        })
+        .unwrap();
+        frame[3] ^= 0xFF;
+        assert!(matches!(
+            APIv1::read_cmd(&frame[..]),
+            Err(ParseError::InvalidHeaderChecksum)
+        ));
+        assert!(dump_frame(&frame).contains("hdr csum: FD (BAD)"));
+    }
+
+    #[test]
+    fn detect_version_finds_a_valid_header() {
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        };
+        // 0x24 followed by a bad header checksum must not be mistaken for v2
+        let mut bytes = vec![0x00, 0x24, 0x10, 0x20, 0x00];
+        bytes.extend(encode_command::<APIv1>(&cmd).unwrap());
+        assert_eq!(detect_version(&mut &bytes[..]), Some(ProtocolVersion::V1));
+
+        let bytes = encode_command::<APIv2>(&cmd).unwrap();
+        assert_eq!(detect_version(&mut &bytes[..]), Some(ProtocolVersion::V2));
+
+        let mut silence = io::repeat(0);
+        assert_eq!(detect_version(&mut silence), None);
+    }
+
+    #[test]
+    fn encode_into_slice_reports_length() {
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        };
+        let frame = encode_command::<APIv2>(&cmd).unwrap();
+        let mut buf = [0; 64];
+        let len = encode_command_into::<APIv2>(&cmd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &frame[..]);
+
+        match encode_command_into::<APIv2>(&cmd, &mut buf[..frame.len() - 1]) {
+            Err(ParseError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::WriteZero),
+            other => panic!("expected an IO error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn frame_len_matches_encoded_frames() {
+        let plain = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        };
+        let with_sub_id = OutgoingCommand {
+            id: 200,
+            sub_id: Some(3),
+            payload: vec![1, 2],
+        };
+        for cmd in &[plain, with_sub_id] {
+            assert_eq!(
+                frame_len::<APIv1>(cmd),
+                encode_command::<APIv1>(cmd).unwrap().len()
+            );
+            assert_eq!(
+                frame_len::<APIv2>(cmd),
+                encode_command::<APIv2>(cmd).unwrap().len()
+            );
+            assert_eq!(
+                frame_len::<WithCrc32<APIv2>>(cmd),
+                encode_command::<WithCrc32<APIv2>>(cmd).unwrap().len()
+            );
+        }
+
+        let empty = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: Vec::new(),
+        };
+        assert_eq!(frame_len::<APIv1>(&empty), 5);
+        assert_eq!(frame_len::<APIv2>(&empty), 6);
+    }
+
+    #[test]
+    fn custom_start_byte_keeps_framing() {
+        type Fork = WithStartByte<APIv2, 0x40>;
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        };
+        let frame = encode_command::<Fork>(&cmd).unwrap();
+        assert_eq!(frame[0], 0x40);
+        assert_eq!(frame[1..], encode_command::<APIv2>(&cmd).unwrap()[1..]);
+        assert!(matches!(
+            Fork::read_cmd(&frame[..]).unwrap(),
+            IncomingCommand::BoardInfo(_)
+        ));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn outer_crc32_wraps_inner_frame() {
+        type Tunneled = WithCrc32<APIv2>;
+        let cmd = OutgoingCommand {
+            id: 86,
+            sub_id: None,
+            payload: board_info_payload(),
+        };
+        let inner = encode_command::<APIv2>(&cmd).unwrap();
+        let frame = encode_command::<Tunneled>(&cmd).unwrap();
+        assert_eq!(frame[..inner.len()], inner[..]);
+        assert_eq!(frame.len(), inner.len() + 4);
+        assert_eq!(frame[inner.len()..], crc32(&inner).to_le_bytes());
+        assert!(matches!(
+            Tunneled::read_cmd(&frame[..]).unwrap(),
+            IncomingCommand::BoardInfo(_)
+        ));
+
+        let mut decoder = crate::decoder::FrameDecoder::<Tunneled>::new();
+        decoder.push(&frame).unwrap();
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Ok(IncomingCommand::BoardInfo(_)))
+        ));
+
+        let mut corrupt = frame.clone();
+        *corrupt.last_mut().unwrap() ^= 0x01;
+        assert!(matches!(
+            Tunneled::read_cmd(&corrupt[..]),
+            Err(ParseError::InvalidPayloadChecksum)
+        ));
+        // Without the outer layer the trailing CRC is left unread
+        assert!(Tunneled::read_cmd(&inner[..]).is_err());
+    }
+
+    #[test]
+    fn short_and_long_payloads_are_told_apart() {
+        let short = &board_info_payload()[..5];
+        let err = parse_command(86, short).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid payload for command 86 (CMD_BOARD_INFO): BoardInfo.board_features: failed to fill whole buffer"
+        );
+
+        let mut long = board_info_payload();
+        long.extend_from_slice(&[0; 3]);
+        assert!(parse_command(86, &long).is_ok());
+        assert!(matches!(
+            parse_command_exact(86, &long),
+            Err(ParseError::TrailingBytes { id: 86, len: 3 })
+        ));
+        assert!(parse_command_exact(86, &board_info_payload()).is_ok());
+    }
+
+    #[test]
+    fn raw_frames_count_link_errors() {
+        let frame = |id| {
+            encode_command::<APIv1>(&OutgoingCommand {
+                id,
+                sub_id: None,
+                payload: vec![id; 3],
+            })
+            .unwrap()
+        };
+        let mut stream = vec![0x00];
+        stream.extend(frame(1));
+        let mut corrupt = frame(2);
+        corrupt[5] ^= 0xFF;
+        stream.extend(corrupt);
+        stream.extend(frame(3));
+        stream.extend(&frame(4)[..5]);
+
+        let mut input = &stream[..];
+        let (mut ids, mut errors) = (Vec::new(), 0);
+        loop {
+            match read_frame_raw::<APIv1>(&mut input) {
+                Ok((id, payload)) => {
+                    assert_eq!(payload, [id; 3]);
+                    ids.push(id);
+                }
+                Err(ParseError::Io(_)) => break,
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(ids, [1, 3]);
+        assert_eq!(errors, 1);
+        assert_eq!(input.len(), 5);
+    }
+
+    #[test]
+    fn frames_are_written_at_once() {
+        struct CountWrites(usize);
+        impl io::Write for CountWrites {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0 += 1;
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut output = CountWrites(0);
+        let cmd = OutgoingCommand {
+            id: 110,
+            sub_id: Some(3),
+            payload: vec![1, 2, 3],
+        };
+        APIv2::write_cmd(&mut output, &cmd).unwrap();
+        assert_eq!(output.0, 1);
     }
 }
@@ -0,0 +1,221 @@
+//! Frames computed with the reference C implementation's framing and `crc16_update`, checked byte
+//! for byte in both directions so any endianness or checksum drift shows up here.
+
+use super::*;
+use crate::commands::*;
+
+fn hex(frame: &str) -> Vec<u8> {
+    frame
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+        .collect()
+}
+
+fn payload<C: Command>(cmd: &C) -> OutgoingCommand {
+    let mut payload = Vec::new();
+    cmd.write_payload(&mut payload).unwrap();
+    OutgoingCommand {
+        id: C::ID,
+        sub_id: None,
+        payload,
+    }
+}
+
+fn control() -> Control {
+    Control {
+        mode: [ControlMode::Angle; 3],
+        axes: [
+            ControlAxis {
+                speed: GimbalSpeed(0),
+                angle: 0,
+            },
+            ControlAxis {
+                speed: GimbalSpeed(300),
+                angle: -910,
+            },
+            ControlAxis {
+                speed: GimbalSpeed(300),
+                angle: 4096,
+            },
+        ],
+    }
+}
+
+#[test]
+fn control_v1() {
+    let frame = hex("3E 43 0F 52 02 02 02 00 00 00 00 2C 01 72 FC 2C 01 00 10 DE");
+    assert_eq!(
+        encode_command::<APIv1>(&payload(&control())).unwrap(),
+        frame
+    );
+    assert_eq!(
+        Control::parse_payload(&mut &frame[4..19]).unwrap(),
+        control()
+    );
+}
+
+#[test]
+fn control_v2() {
+    let frame = hex("24 43 0F 52 02 02 02 00 00 00 00 2C 01 72 FC 2C 01 00 10 16 DF");
+    assert_eq!(
+        encode_command::<APIv2>(&payload(&control())).unwrap(),
+        frame
+    );
+    assert_eq!(
+        Control::parse_payload(&mut &frame[4..19]).unwrap(),
+        control()
+    );
+}
+
+#[test]
+fn board_info_response_v2() {
+    let frame =
+        hex("24 56 16 6C 03 7F 0A 1C 25 00 01 78 56 34 12 00 00 00 00 00 00 00 00 00 00 00 93 5C");
+    let info = match APIv2::read_cmd(&frame[..]).unwrap() {
+        IncomingCommand::BoardInfo(info) => info,
+        other => panic!("expected BoardInfo, got {:?}", other),
+    };
+    assert_eq!(info.board_ver, 3);
+    assert_eq!(info.firmware_ver, 2687);
+    assert_eq!(
+        info.state_flags1,
+        BoardInfoStateFlags::INIT_STEP1_DONE
+            | BoardInfoStateFlags::INIT_STEP2_DONE
+            | BoardInfoStateFlags::STARTUP_AUTO_ROUTINE_DONE
+    );
+    assert_eq!(
+        info.board_features,
+        BoardInfoFeatures::THREE_AXIS
+            | BoardInfoFeatures::ENCODERS
+            | BoardInfoFeatures::CURRENT_SENSOR
+    );
+    assert_eq!(
+        info.connection_flag,
+        BoardInfoConnectionFlags::CONNECTION_USB
+    );
+    assert_eq!(info.frw_extra_id, 0x1234_5678);
+
+    assert_eq!(encode_command::<APIv2>(&payload(&info)).unwrap(), frame);
+}
+
+#[test]
+fn get_angles_response_v2() {
+    let frame = hex("24 49 12 5B D3 FF 00 00 00 00 8E 03 8E 03 F4 FF 00 F0 FC EF 21 00 04 36");
+    let angles = GetAnglesResponse {
+        axes: PerAxis([
+            AxisAngles {
+                imu_angle: -45,
+                target_angle: 0,
+                target_speed: GimbalSpeed(0),
+            },
+            AxisAngles {
+                imu_angle: 910,
+                target_angle: 910,
+                target_speed: GimbalSpeed(-12),
+            },
+            AxisAngles {
+                imu_angle: -4096,
+                target_angle: -4100,
+                target_speed: GimbalSpeed(33),
+            },
+        ]),
+    };
+    assert_eq!(
+        APIv2::read_cmd(&frame[..]).unwrap(),
+        IncomingCommand::GetAnglesResponse(angles.clone())
+    );
+    assert_eq!(encode_command::<APIv2>(&payload(&angles)).unwrap(), frame);
+}
+
+/// The header checksum covers only the id and length, while the CRC covers everything after the
+/// start byte, header checksum included
+#[test]
+fn control_v2_header_bytes() {
+    let frame = encode_command::<APIv2>(&payload(&control())).unwrap();
+    assert_eq!(frame.len(), 4 + 15 + 2);
+    assert_eq!(frame[0], 0x24, "start byte");
+    assert_eq!(frame[1], 0x43, "command id");
+    assert_eq!(frame[2], 0x0F, "payload length");
+    assert_eq!(frame[3], 0x52, "header checksum 0x43 + 0x0F");
+    assert_eq!(
+        u16::from_le_bytes([frame[19], frame[20]]),
+        0xDF16,
+        "little-endian CRC16"
+    );
+    assert_eq!(crc16(&frame[1..19]), 0xDF16);
+    assert_ne!(
+        crc16(&frame[..19]),
+        0xDF16,
+        "start byte is not part of the CRC"
+    );
+}
+
+#[test]
+fn control_yaw_only_v1() {
+    let yaw = Control::axis_only(
+        Axis::Yaw,
+        ControlMode::Angle,
+        ControlAxis {
+            speed: GimbalSpeed(300),
+            angle: 4096,
+        },
+    );
+    let frame = hex("3E 43 0F 52 00 00 02 00 00 00 00 00 00 00 00 2C 01 00 10 3F");
+    assert_eq!(encode_command::<APIv1>(&payload(&yaw)).unwrap(), frame);
+}
+
+/// CMD_MOTORS_ON, like many commands, has no payload
+fn motors_on() -> OutgoingCommand {
+    OutgoingCommand {
+        id: 77,
+        sub_id: None,
+        payload: Vec::new(),
+    }
+}
+
+fn assert_empty_frame<P: Parser>(frame: &[u8]) {
+    assert_eq!(encode_command::<P>(&motors_on()).unwrap(), frame);
+
+    let empty = IncomingCommand::Unknown {
+        id: 77,
+        sub_id: None,
+        payload: Vec::new(),
+    };
+    assert_eq!(P::read_cmd(frame).unwrap(), empty);
+
+    let mut decoder = crate::decoder::FrameDecoder::<P>::new();
+    decoder.push(&frame[..frame.len() - 1]).unwrap();
+    assert!(decoder.next_frame().is_none());
+    decoder.push(&frame[frame.len() - 1..]).unwrap();
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), empty);
+    assert_eq!(decoder.buffered(), 0);
+}
+
+#[test]
+fn empty_payload_v1() {
+    // The summed checksum of nothing is 0
+    assert_empty_frame::<APIv1>(&hex("3E 4D 00 4D 00"));
+}
+
+#[test]
+fn empty_payload_v2() {
+    // The CRC still covers the header
+    assert_empty_frame::<APIv2>(&hex("24 4D 00 4D 44 0A"));
+}
+
+#[test]
+fn max_payload_checksum_wraps_v1() {
+    // 255 * 0xFF = 0xFE01, so the sum wraps around to 0x01 many times over
+    let cmd = OutgoingCommand {
+        id: 200,
+        sub_id: None,
+        payload: vec![0xFF; 255],
+    };
+    let frame = encode_command::<APIv1>(&cmd).unwrap();
+    assert_eq!(frame[..4], [0x3E, 200, 255, 199]);
+    assert_eq!(frame.len(), 4 + 255 + 1);
+    assert_eq!(frame.last(), Some(&0x01));
+
+    let (id, payload) = read_frame_raw::<APIv1>(&mut &frame[..]).unwrap();
+    assert_eq!((id, payload), (200, &cmd.payload[..]));
+}
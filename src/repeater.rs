@@ -0,0 +1,174 @@
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::commands::{Control, OutgoingCommand};
+use crate::parser::{encode_command, ParseError, Parser};
+
+enum Message {
+    Update(Vec<u8>),
+    Stop,
+}
+
+/// Resends a `Control` on a background thread at a fixed interval, so the gimbal keeps moving
+/// until it's stopped.
+///
+/// Boards stop following a control command after the timeout set with `CMD_CONTROL_CONFIG`, so
+/// the interval has to be shorter than that. The command is sent once straight away and then
+/// after every interval. Dropping the repeater stops it too, but ignores any write error.
+pub struct ControlRepeater<P: Parser> {
+    sender: Sender<Message>,
+    thread: Option<JoinHandle<Result<(), ParseError>>>,
+    _parser: PhantomData<P>,
+}
+
+fn frame<P: Parser>(control: &Control) -> Result<Vec<u8>, ParseError> {
+    encode_command::<P>(&OutgoingCommand::from_command(control)?)
+}
+
+impl<P: Parser> ControlRepeater<P> {
+    /// Starts sending `control` to `writer`, which is usually a clone of the client's transport.
+    pub fn spawn<W>(
+        mut writer: W,
+        control: &Control,
+        interval: Duration,
+    ) -> Result<Self, ParseError>
+    where
+        W: Write + Send + 'static,
+    {
+        let mut frame = frame::<P>(control)?;
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            writer.write_all(&frame)?;
+            writer.flush()?;
+            match receiver.recv_timeout(interval) {
+                Ok(Message::Update(update)) => frame = update,
+                Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        });
+        Ok(Self {
+            sender,
+            thread: Some(thread),
+            _parser: PhantomData,
+        })
+    }
+
+    /// Sends `control` from now on instead, starting immediately.
+    pub fn set(&self, control: &Control) -> Result<(), ParseError> {
+        // If the thread has stopped, the error is returned from stop instead
+        let _ = self.sender.send(Message::Update(frame::<P>(control)?));
+        Ok(())
+    }
+
+    /// Stops sending and returns the write error that ended the thread early, if there was one.
+    /// If the thread panicked, the panic is resumed here.
+    pub fn stop(mut self) -> Result<(), ParseError> {
+        match self.join() {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+
+    fn join(&mut self) -> Option<thread::Result<Result<(), ParseError>>> {
+        let _ = self.sender.send(Message::Stop);
+        self.thread.take().map(JoinHandle::join)
+    }
+}
+
+impl<P: Parser> Drop for ControlRepeater<P> {
+    fn drop(&mut self) {
+        // Resuming a panic here would abort if the repeater is dropped while already unwinding
+        let _ = self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Command, ControlAxis, ControlMode, GimbalSpeed, Transmit};
+    use crate::decoder::parse_all;
+    use crate::parser::APIv2;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn control(speed: i16) -> Control {
+        Control {
+            mode: [ControlMode::Speed; 3],
+            axes: [ControlAxis {
+                speed: GimbalSpeed(speed),
+                angle: 0,
+            }; 3],
+        }
+    }
+
+    #[test]
+    fn resends_until_stopped() {
+        let output = Shared::default();
+        let repeater =
+            ControlRepeater::<APIv2>::spawn(output.clone(), &control(10), Duration::from_millis(1))
+                .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        repeater.set(&control(20)).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        repeater.stop().unwrap();
+
+        let sent = output.0.lock().unwrap().clone();
+        let (frames, rest) = parse_all::<APIv2>(&sent);
+        assert!(rest.is_empty());
+        // At least the first frame and the one sent straight after the update
+        assert!(frames.len() >= 2);
+        assert!(frames.iter().all(|frame| frame.id() == Control::ID));
+
+        // Every frame is the same length, so the first and last can be picked out and decoded
+        let frame_len = sent.len() / frames.len();
+        let payload = |frame: &[u8]| Control::from_slice(&frame[4..frame_len - 2]).unwrap();
+        assert_eq!(payload(&sent[..frame_len]), control(10));
+        assert_eq!(payload(&sent[sent.len() - frame_len..]), control(20));
+
+        // Nothing is sent once stopped
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(output.0.lock().unwrap().len(), sent.len());
+    }
+
+    struct Panics;
+
+    impl Write for Panics {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            panic!("transport panicked")
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drop_ignores_a_panicked_thread() {
+        let repeater =
+            ControlRepeater::<APIv2>::spawn(Panics, &control(10), Duration::from_millis(1))
+                .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        drop(repeater);
+
+        let repeater =
+            ControlRepeater::<APIv2>::spawn(Panics, &control(10), Duration::from_millis(1))
+                .unwrap();
+        let stopped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| repeater.stop()));
+        assert!(stopped.is_err());
+    }
+}
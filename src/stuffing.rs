@@ -0,0 +1,193 @@
+use std::io::{self, Read, Write};
+
+/// Undoes byte-stuffing added by a transport before the bytes reach the frame decoder.
+///
+/// SimpleBGC itself never escapes bytes, so this is only needed when frames are tunneled over a
+/// link that escapes some bytes of its own, such as the start byte, and hands them on unchanged.
+/// Implementations are fed the received bytes in whatever chunks they arrive in, so an escape
+/// sequence can be split across calls.
+pub trait Unstuff {
+    /// Appends the unescaped form of `input` to `output`.
+    fn unstuff(&mut self, input: &[u8], output: &mut Vec<u8>);
+}
+
+/// Passes bytes through unchanged, for transports that don't stuff bytes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Passthrough;
+
+impl Unstuff for Passthrough {
+    fn unstuff(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        output.extend_from_slice(input);
+    }
+}
+
+/// Removes an escape byte and XORs the byte after it with a mask, the scheme used by SLIP-like
+/// and HDLC-like links. With HDLC's `0x7D` and `0x20`, `7D 04` is read as `24`.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeXor {
+    escape: u8,
+    mask: u8,
+    escaped: bool,
+}
+
+impl EscapeXor {
+    pub fn new(escape: u8, mask: u8) -> Self {
+        Self {
+            escape,
+            mask,
+            escaped: false,
+        }
+    }
+}
+
+impl Unstuff for EscapeXor {
+    fn unstuff(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for byte in input {
+            if self.escaped {
+                output.push(byte ^ self.mask);
+                self.escaped = false;
+            } else if *byte == self.escape {
+                self.escaped = true;
+            } else {
+                output.push(*byte);
+            }
+        }
+    }
+}
+
+/// Wraps a transport so bytes read from it are unstuffed, for use between the transport and a
+/// `Client` or `FrameDecoder`. Writes go to the transport unchanged, since the link stuffs
+/// outgoing bytes itself.
+#[derive(Debug)]
+pub struct UnstuffReader<R, U = Passthrough> {
+    inner: R,
+    unstuff: U,
+    /// Unstuffed bytes not yet returned from `read`
+    buf: Vec<u8>,
+}
+
+impl<R> UnstuffReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_unstuff(inner, Passthrough)
+    }
+}
+
+impl<R, U> UnstuffReader<R, U> {
+    pub fn with_unstuff(inner: R, unstuff: U) -> Self {
+        Self {
+            inner,
+            unstuff,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read, U: Unstuff> Read for UnstuffReader<R, U> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0; 64];
+        // A chunk holding only an escape byte produces nothing, so keep reading until it does
+        while self.buf.is_empty() && !buf.is_empty() {
+            let read = self.inner.read(&mut raw)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            self.unstuff.unstuff(&raw[..read], &mut self.buf);
+        }
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<R: Write, U> Write for UnstuffReader<R, U> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{IncomingCommand, OutgoingCommand};
+    use crate::decoder::FrameDecoder;
+    use crate::parser::{encode_command, APIv2, Parser};
+
+    /// Gives out one byte per read, so escape sequences are split across reads
+    struct Trickle(Vec<u8>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0.remove(0);
+            Ok(1)
+        }
+    }
+
+    fn stuff(frame: &[u8]) -> Vec<u8> {
+        let mut stuffed = Vec::new();
+        for byte in frame {
+            match byte {
+                0x24 | 0x7D => stuffed.extend([0x7D, byte ^ 0x20]),
+                _ => stuffed.push(*byte),
+            }
+        }
+        stuffed
+    }
+
+    fn decode<R: Read>(mut reader: R) -> Vec<IncomingCommand> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).unwrap();
+        let mut decoder = FrameDecoder::<APIv2>::new();
+        decoder.push(&bytes).unwrap();
+        std::iter::from_fn(|| decoder.next_frame())
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn unstuffs_split_escapes() {
+        let frame = encode_command::<APIv2>(&OutgoingCommand {
+            id: 200,
+            sub_id: None,
+            payload: vec![0x24, 0x7D, 0x01],
+        })
+        .unwrap();
+        let stuffed = stuff(&frame);
+        assert_eq!(stuffed[0], 0x7D);
+        assert!(!stuffed.contains(&APIv2::START_BYTE));
+
+        let reader = UnstuffReader::with_unstuff(Trickle(stuffed), EscapeXor::new(0x7D, 0x20));
+        let cmds = decode(reader);
+        assert_eq!(
+            cmds,
+            [IncomingCommand::Unknown {
+                id: 200,
+                sub_id: None,
+                payload: vec![0x24, 0x7D, 0x01],
+            }]
+        );
+    }
+
+    #[test]
+    fn passes_through_by_default() {
+        let frame = encode_command::<APIv2>(&OutgoingCommand {
+            id: 200,
+            sub_id: None,
+            payload: vec![0x7D],
+        })
+        .unwrap();
+        let cmds = decode(UnstuffReader::new(Trickle(frame)));
+        assert_eq!(cmds.len(), 1);
+    }
+}